@@ -0,0 +1,199 @@
+use mazeio_shared::tokio::time::Instant;
+use mazeio_shared::{Direction, Position, ProtoMaze};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Time between autopilot moves, well within what servers let players move.
+/// Clicked on cells are walked to at the same pace.
+pub const AUTOPILOT_STEP: Duration = Duration::from_millis(100);
+
+/// Showing the way to the goal and walking it for the player,
+/// for game modes where the server allows it
+#[derive(Debug, Default)]
+pub struct Assist {
+    /// whether the server allows assists in this game mode
+    pub available: bool,
+    /// draw the way to the destination
+    pub hint: bool,
+    pub autopilot: bool,
+    /// cell to head for instead of the goal
+    pub target: Option<Position>,
+    /// moves left on the way to a cell that was clicked on
+    walk: VecDeque<Direction>,
+    /// when the autopilot last moved
+    last_step: Option<Instant>,
+}
+
+impl Assist {
+    pub fn toggle_hint(&mut self) {
+        self.hint = self.available && !self.hint;
+    }
+
+    pub fn toggle_autopilot(&mut self) {
+        self.autopilot = self.available && !self.autopilot;
+        self.last_step = None;
+    }
+
+    /// Queue the moves along the shortest way from pos to cell instead of
+    /// following the autopilot, returning whether there is such a way
+    pub fn walk_to(&mut self, maze: &ProtoMaze, pos: &Position, cell: &Position) -> bool {
+        let path = match maze.shortest_path(pos, cell) {
+            Some(path) if self.available => path,
+            _ => return false,
+        };
+        self.stop();
+        let mut from = pos.clone();
+        for next in path {
+            self.walk.extend(Direction::between(&from, &next));
+            from = next;
+        }
+        true
+    }
+
+    /// Take back control, stopping the autopilot and any walk to a clicked cell
+    pub fn stop(&mut self) {
+        self.autopilot = false;
+        self.walk.clear();
+    }
+
+    /// Head for the marker after the current target, or back
+    /// to the goal after the last one
+    pub fn next_target(&mut self, markers: &[(u32, u32)]) {
+        let next = match &self.target {
+            Some(target) => markers
+                .iter()
+                .skip_while(|&&cell| cell != (target.x, target.y))
+                .nth(1),
+            None => markers.first(),
+        };
+        self.target = next.map(|&(x, y)| Position::new(x, y));
+    }
+
+    /// The chosen target, or else the goal if the game has one we know of
+    pub fn destination(&self, maze: &ProtoMaze) -> Option<Position> {
+        self.target.clone().or_else(|| maze.goal.clone())
+    }
+
+    /// The way from pos to the destination, to draw when hints are on
+    pub fn route(&self, maze: &ProtoMaze, pos: &Position) -> Option<Vec<Position>> {
+        if !self.hint {
+            return None;
+        }
+        maze.shortest_path(pos, &self.destination(maze)?)
+    }
+
+    /// Which way to move from pos, if walking to a clicked cell or the
+    /// autopilot is on, and a move is due at now. The autopilot turns
+    /// itself off once there, or when there is no way there.
+    pub fn autopilot_step(
+        &mut self,
+        maze: &ProtoMaze,
+        pos: &Position,
+        now: Instant,
+    ) -> Option<Direction> {
+        if !self.autopilot && self.walk.is_empty() {
+            return None;
+        }
+        if self
+            .last_step
+            .is_some_and(|last| now.duration_since(last) < AUTOPILOT_STEP)
+        {
+            return None;
+        }
+        if let Some(dir) = self.walk.pop_front() {
+            self.last_step = Some(now);
+            return Some(dir);
+        }
+        let next = self
+            .destination(maze)
+            .and_then(|destination| maze.shortest_path(pos, &destination))
+            .and_then(|path| path.into_iter().next());
+        match next {
+            Some(next) => {
+                self.last_step = Some(now);
+                Direction::between(pos, &next)
+            }
+            None => {
+                self.autopilot = false;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mazeio_shared::CellType;
+
+    /// a single corridor along the middle row, walled in
+    fn corridor_maze() -> ProtoMaze {
+        let mut maze = ProtoMaze {
+            width: 6,
+            height: 3,
+            cells: vec![CellType::Wall as i32; 18],
+            goal: Some(Position::new(4, 1)),
+            seed: 1,
+        };
+        for x in 1..5 {
+            maze.set(x, 1, CellType::Open);
+        }
+        maze
+    }
+
+    #[test]
+    fn autopilot_walks_to_the_destination_at_its_own_pace() {
+        let maze = corridor_maze();
+        let mut assist = Assist::default();
+        assist.toggle_autopilot();
+        assert!(!assist.autopilot, "only when the server allows it");
+
+        assist.available = true;
+        assist.toggle_autopilot();
+        let start = Instant::now();
+        let mut pos = Position::new(1, 1);
+        assert_eq!(
+            assist.autopilot_step(&maze, &pos, start),
+            Some(Direction::Right)
+        );
+        pos.x += 1;
+        assert_eq!(assist.autopilot_step(&maze, &pos, start), None);
+        let later = start + AUTOPILOT_STEP;
+        assert_eq!(
+            assist.autopilot_step(&maze, &pos, later),
+            Some(Direction::Right)
+        );
+
+        // markers take over from the goal, until we run out of them
+        assist.next_target(&[(1, 1), (3, 1)]);
+        assert_eq!(assist.destination(&maze), Some(Position::new(1, 1)));
+        assert_eq!(
+            assist.autopilot_step(&maze, &pos, later + AUTOPILOT_STEP),
+            Some(Direction::Left)
+        );
+        assist.next_target(&[(1, 1), (3, 1)]);
+        assist.next_target(&[(1, 1), (3, 1)]);
+        assert_eq!(assist.destination(&maze), maze.goal);
+
+        pos.x = 4;
+        let arrived = later + AUTOPILOT_STEP * 2;
+        assert_eq!(assist.autopilot_step(&maze, &pos, arrived), None);
+        assert!(!assist.autopilot);
+
+        // a clicked cell is walked to one queued move at a time
+        assert!(assist.walk_to(&maze, &pos, &Position::new(2, 1)));
+        assert!(!assist.walk_to(&maze, &pos, &Position::new(2, 0)));
+        let mut now = arrived;
+        for _ in 0..2 {
+            now += AUTOPILOT_STEP;
+            assert_eq!(
+                assist.autopilot_step(&maze, &pos, now),
+                Some(Direction::Left)
+            );
+        }
+        assert_eq!(
+            assist.autopilot_step(&maze, &pos, now + AUTOPILOT_STEP),
+            None
+        );
+    }
+}
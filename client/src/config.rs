@@ -0,0 +1,404 @@
+use clap::Parser;
+use crossterm::event::KeyCode;
+use mazeio_shared::names;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tui::style::Color;
+
+/// The mazeio terminal client.
+///
+/// Settings are taken from command line flags first, then environment
+/// variables, then the config file, then defaults.
+#[derive(Parser, Debug, Default)]
+#[clap(about, version, author)]
+pub struct Args {
+    /// Path of a TOML config file [default: <config dir>/mazeio/client.toml, if present]
+    #[clap(short, long, env = "MAZEIO_CLIENT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Server to connect to [default: http://[::1]:50051]
+    #[clap(short, long, env = "MAZEIO_SERVER")]
+    pub server: Option<String>,
+    /// Name shown to other players [default: $USER]
+    #[clap(short, long, env = "MAZEIO_NAME")]
+    pub name: Option<String>,
+    /// Watch the game without joining it
+    #[clap(long)]
+    pub spectate: bool,
+    /// Watch a replay file recorded by the server instead of joining a game
+    #[clap(short, long)]
+    pub replay: Option<PathBuf>,
+    /// Browse the server's best times and wins instead of joining a game
+    #[clap(long)]
+    pub leaderboard: bool,
+}
+
+/// Contents of the config file, every key is optional
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub server: Option<String>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub colors: ColorConfig,
+    /// key name to action, e.g. `q = "quit"`, overriding the defaults
+    #[serde(default)]
+    pub keys: HashMap<String, Action>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ColorConfig {
+    pub player: Option<String>,
+    pub opponent: Option<String>,
+    pub trail: Option<String>,
+    pub goal: Option<String>,
+    pub accent: Option<String>,
+    pub hint: Option<String>,
+}
+
+/// Something a key can be bound to
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    /// start typing a chat message
+    Chat,
+    /// show or hide the minimap
+    Minimap,
+    /// show or hide the trail of visited cells
+    Trail,
+    /// drop a marker where we stand, or pick it up again
+    Marker,
+    /// show or hide the way to the goal or target
+    Hint,
+    /// start or stop moving along the way to the goal or target by itself
+    Autopilot,
+    /// head for the next marker instead of the goal
+    Target,
+    Quit,
+    /// removes a default binding
+    None,
+}
+
+/// Colors used when drawing the game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub player: Color,
+    pub opponent: Color,
+    pub trail: Color,
+    pub goal: Color,
+    pub accent: Color,
+    /// the way to the goal, when hints are on
+    pub hint: Color,
+}
+
+/// colors opponents get after the configured one, so they can be told apart
+const OPPONENT_PALETTE: [Color; 5] = [
+    Color::Yellow,
+    Color::LightMagenta,
+    Color::LightGreen,
+    Color::LightRed,
+    Color::White,
+];
+
+impl Theme {
+    /// The color of the opponent given this palette slot, wrapping
+    /// around once there are more opponents than colors
+    pub fn opponent_color(&self, slot: usize) -> Color {
+        match slot % (OPPONENT_PALETTE.len() + 1) {
+            0 => self.opponent,
+            i => OPPONENT_PALETTE[i - 1],
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            player: Color::Cyan,
+            opponent: Color::Red,
+            trail: Color::Blue,
+            goal: Color::Green,
+            accent: Color::Magenta,
+            hint: Color::Yellow,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    keys: HashMap<KeyCode, Action>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let keys = [
+            (KeyCode::Char('a'), Action::MoveLeft),
+            (KeyCode::Char('d'), Action::MoveRight),
+            (KeyCode::Char('w'), Action::MoveUp),
+            (KeyCode::Char('s'), Action::MoveDown),
+            (KeyCode::Char('h'), Action::MoveLeft),
+            (KeyCode::Char('l'), Action::MoveRight),
+            (KeyCode::Char('k'), Action::MoveUp),
+            (KeyCode::Char('j'), Action::MoveDown),
+            (KeyCode::Left, Action::MoveLeft),
+            (KeyCode::Right, Action::MoveRight),
+            (KeyCode::Up, Action::MoveUp),
+            (KeyCode::Down, Action::MoveDown),
+            (KeyCode::Enter, Action::Chat),
+            (KeyCode::Char('m'), Action::Minimap),
+            (KeyCode::Char('t'), Action::Trail),
+            (KeyCode::Char('b'), Action::Marker),
+            (KeyCode::Char('p'), Action::Hint),
+            (KeyCode::Char('o'), Action::Autopilot),
+            (KeyCode::Char('g'), Action::Target),
+            (KeyCode::Esc, Action::Quit),
+        ]
+        .into_iter()
+        .collect();
+        Self { keys }
+    }
+}
+
+impl KeyBindings {
+    pub fn get(&self, key: &KeyCode) -> Option<Action> {
+        self.keys.get(key).copied()
+    }
+
+    /// Every key bound to action, e.g. "a/h/←", for showing to the player
+    pub fn describe(&self, action: Action) -> String {
+        let mut names: Vec<String> = self
+            .keys
+            .iter()
+            .filter(|(_, &a)| a == action)
+            .map(|(key, _)| key_name(key))
+            .collect();
+        // letters first, then named keys, so it reads the same every frame
+        names.sort_by_key(|name| (name.chars().count() > 1, name.clone()));
+        names.join("/")
+    }
+
+    fn apply(&mut self, overrides: HashMap<String, Action>) -> Result<(), String> {
+        for (name, action) in overrides {
+            let key = parse_key(&name)?;
+            if action == Action::None {
+                self.keys.remove(&key);
+            } else {
+                self.keys.insert(key, action);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fully resolved client settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server: String,
+    pub name: String,
+    pub theme: Theme,
+    pub keys: KeyBindings,
+    pub spectate: bool,
+    pub replay: Option<PathBuf>,
+    pub leaderboard: bool,
+}
+
+impl Config {
+    /// Read the command line, environment and config file
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let args = Args::parse();
+        let default_path = dirs::config_dir().map(|dir| dir.join("mazeio").join("client.toml"));
+        let file = match (&args.config, default_path) {
+            (Some(path), _) => FileConfig::read(path)?,
+            (None, Some(path)) if path.exists() => FileConfig::read(&path)?,
+            _ => FileConfig::default(),
+        };
+        Self::merge(args, file)
+    }
+
+    /// Combine settings, preferring args (which already include env vars) over the file
+    pub fn merge(args: Args, file: FileConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let default_theme = Theme::default();
+        let color = |name: Option<String>, default: Color| match name {
+            Some(name) => parse_color(&name),
+            None => Ok(default),
+        };
+        let theme = Theme {
+            player: color(file.colors.player, default_theme.player)?,
+            opponent: color(file.colors.opponent, default_theme.opponent)?,
+            trail: color(file.colors.trail, default_theme.trail)?,
+            goal: color(file.colors.goal, default_theme.goal)?,
+            accent: color(file.colors.accent, default_theme.accent)?,
+            hint: color(file.colors.hint, default_theme.hint)?,
+        };
+        let mut keys = KeyBindings::default();
+        keys.apply(file.keys)?;
+        Ok(Config {
+            server: args
+                .server
+                .or(file.server)
+                .unwrap_or_else(|| "http://[::1]:50051".to_string()),
+            name: args
+                .name
+                .or(file.name)
+                .or_else(|| {
+                    std::env::var("USER")
+                        .ok()
+                        .and_then(|login| name_from_login(&login))
+                })
+                .unwrap_or_else(|| "player".to_string()),
+            theme,
+            keys,
+            spectate: args.spectate,
+            replay: args.replay,
+            leaderboard: args.leaderboard,
+        })
+    }
+}
+
+impl FileConfig {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into())
+    }
+}
+
+/// A name the server takes, made from a login name, which could be too long
+/// or look like user@domain or DOMAIN\user. None if nothing usable is left.
+fn name_from_login(login: &str) -> Option<String> {
+    let user = login.rsplit('\\').next().unwrap_or(login);
+    let user = user.split('@').next().unwrap_or(user);
+    let name: String = user
+        .chars()
+        .filter(|c| names::is_allowed(*c))
+        .take(names::MAX_NAME_LEN)
+        .collect();
+    let name = name.trim();
+    (!name.is_empty() && !names::is_reserved(name)).then(|| name.to_string())
+}
+
+fn parse_key(name: &str) -> Result<KeyCode, String> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(c));
+    }
+    match name.to_lowercase().as_str() {
+        "left" => Ok(KeyCode::Left),
+        "right" => Ok(KeyCode::Right),
+        "up" => Ok(KeyCode::Up),
+        "down" => Ok(KeyCode::Down),
+        "esc" => Ok(KeyCode::Esc),
+        "enter" => Ok(KeyCode::Enter),
+        "tab" => Ok(KeyCode::Tab),
+        "backspace" => Ok(KeyCode::Backspace),
+        "space" => Ok(KeyCode::Char(' ')),
+        _ => Err(format!("Unknown key '{}'", name)),
+    }
+}
+
+fn key_name(key: &KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Backspace => "Backspace".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Color names as in tui, e.g. "light-red", or "#rrggbb"
+fn parse_color(name: &str) -> Result<Color, String> {
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() == 6 {
+            if let Ok(rgb) = u32::from_str_radix(hex, 16) {
+                return Ok(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+            }
+        }
+        return Err(format!("Invalid color '{}', expected #rrggbb", name));
+    }
+    match name.to_lowercase().replace('_', "-").as_str() {
+        "black" => Ok(Color::Black),
+        "red" => Ok(Color::Red),
+        "green" => Ok(Color::Green),
+        "yellow" => Ok(Color::Yellow),
+        "blue" => Ok(Color::Blue),
+        "magenta" => Ok(Color::Magenta),
+        "cyan" => Ok(Color::Cyan),
+        "gray" => Ok(Color::Gray),
+        "dark-gray" => Ok(Color::DarkGray),
+        "light-red" => Ok(Color::LightRed),
+        "light-green" => Ok(Color::LightGreen),
+        "light-yellow" => Ok(Color::LightYellow),
+        "light-blue" => Ok(Color::LightBlue),
+        "light-magenta" => Ok(Color::LightMagenta),
+        "light-cyan" => Ok(Color::LightCyan),
+        "white" => Ok(Color::White),
+        _ => Err(format!("Unknown color '{}'", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_overrides_defaults() {
+        let file: FileConfig = toml::from_str(
+            r##"
+            name = "from-file"
+            server = "http://example.com:50051"
+
+            [colors]
+            player = "#ff8000"
+
+            [keys]
+            q = "quit"
+            w = "none"
+            "##,
+        )
+        .unwrap();
+        let args = Args {
+            name: Some("from-args".to_string()),
+            ..Args::default()
+        };
+        let config = Config::merge(args, file).unwrap();
+        assert_eq!(config.name, "from-args");
+        assert_eq!(config.server, "http://example.com:50051");
+        assert_eq!(config.theme.player, Color::Rgb(0xff, 0x80, 0x00));
+        assert_eq!(config.theme.opponent, Theme::default().opponent);
+        assert_eq!(config.keys.get(&KeyCode::Char('q')), Some(Action::Quit));
+        assert_eq!(config.keys.get(&KeyCode::Char('w')), None);
+        assert_eq!(config.keys.get(&KeyCode::Up), Some(Action::MoveUp));
+    }
+
+    #[test]
+    fn login_names_are_made_acceptable() {
+        assert_eq!(name_from_login("ann@corp.example").as_deref(), Some("ann"));
+        assert_eq!(name_from_login("CORP\\bob").as_deref(), Some("bob"));
+        assert_eq!(
+            name_from_login("a-very-long-login-name-indeed").as_deref(),
+            Some("a-very-long-login-na")
+        );
+        assert_eq!(name_from_login("admin"), None);
+        assert_eq!(name_from_login("$$$"), None);
+    }
+
+    #[test]
+    fn describes_bindings() {
+        let keys = KeyBindings::default();
+        assert_eq!(keys.describe(Action::MoveUp), "k/w/↑");
+        assert_eq!(keys.describe(Action::Quit), "Esc");
+    }
+}
@@ -0,0 +1,139 @@
+extern crate mazeio_shared;
+use mazeio_shared::*;
+use std::collections::{HashMap, VecDeque};
+
+/// How far behind the newest server state remote players are rendered.
+/// This gives us a little slack so that jittery delivery still has a
+/// later snapshot to interpolate towards.
+pub const INTERPOLATION_DELAY_MS: u64 = 150;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Snapshot {
+    server_time_ms: u64,
+    pos: Position,
+}
+
+/// Buffers timestamped positions of remote players, so they can be drawn
+/// a fixed delay in the past instead of jumping whenever a message arrives
+#[derive(Debug, Default)]
+pub struct RemoteBuffer {
+    snapshots: HashMap<String, VecDeque<Snapshot>>,
+    /// smallest observed (local receive time - server send time)
+    clock_offset_ms: Option<i64>,
+}
+
+impl RemoteBuffer {
+    pub fn push(&mut self, id: &str, server_time_ms: u64, pos: Position, local_time_ms: u64) {
+        // the least delayed message gives the best estimate of the clock offset
+        let offset = local_time_ms as i64 - server_time_ms as i64;
+        self.clock_offset_ms = Some(self.clock_offset_ms.map_or(offset, |o| o.min(offset)));
+
+        let buffer = self.snapshots.entry(id.to_string()).or_default();
+        // ignore out of order delivery
+        if let Some(last) = buffer.back() {
+            if last.server_time_ms > server_time_ms {
+                return;
+            }
+        }
+        buffer.push_back(Snapshot {
+            server_time_ms,
+            pos,
+        });
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.snapshots.remove(id);
+    }
+
+    /// Forget every player whose id doesn't match the predicate
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        self.snapshots.retain(|id, _| keep(id));
+    }
+
+    /// Server time that should currently be rendered
+    fn render_time(&self, local_time_ms: u64) -> Option<u64> {
+        let offset = self.clock_offset_ms?;
+        let server_now = local_time_ms as i64 - offset;
+        Some(
+            server_now
+                .saturating_sub(INTERPOLATION_DELAY_MS as i64)
+                .max(0) as u64,
+        )
+    }
+
+    /// Interpolated positions of every buffered player at the current render time
+    pub fn positions(&mut self, local_time_ms: u64) -> HashMap<String, Position> {
+        let render_time = match self.render_time(local_time_ms) {
+            Some(t) => t,
+            None => return HashMap::new(),
+        };
+        self.snapshots
+            .iter_mut()
+            .filter_map(|(id, buffer)| {
+                // drop snapshots we will no longer interpolate from
+                while buffer.len() > 2 && buffer[1].server_time_ms <= render_time {
+                    buffer.pop_front();
+                }
+                interpolate(buffer, render_time).map(|pos| (id.clone(), pos))
+            })
+            .collect()
+    }
+}
+
+fn interpolate(buffer: &VecDeque<Snapshot>, render_time: u64) -> Option<Position> {
+    let first = buffer.front()?;
+    if render_time <= first.server_time_ms {
+        return Some(first.pos.clone());
+    }
+    for (from, to) in buffer.iter().zip(buffer.iter().skip(1)) {
+        if from.server_time_ms <= render_time && render_time < to.server_time_ms {
+            // only slide between neighbouring cells, anything else
+            // (e.g. a respawn) would pass through walls
+            let dist = (from.pos.x as i64 - to.pos.x as i64).abs()
+                + (from.pos.y as i64 - to.pos.y as i64).abs();
+            if dist != 1 {
+                return Some(from.pos.clone());
+            }
+            let t = (render_time - from.server_time_ms) as f64
+                / (to.server_time_ms - from.server_time_ms) as f64;
+            let lerp = |a: u32, b: u32| (a as f64 + (b as f64 - a as f64) * t).round() as u32;
+            return Some(Position::new(
+                lerp(from.pos.x, to.pos.x),
+                lerp(from.pos.y, to.pos.y),
+            ));
+        }
+    }
+    buffer.back().map(|s| s.pos.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_in_the_past() {
+        let mut buffer = RemoteBuffer::default();
+        buffer.push("a", 1000, Position::new(1, 1), 1000);
+        buffer.push("a", 1100, Position::new(2, 1), 1100);
+        buffer.push("a", 1200, Position::new(3, 1), 1200);
+
+        // render time 1080 is most of the way from (1,1) to (2,1)
+        let positions = buffer.positions(1000 + INTERPOLATION_DELAY_MS + 80);
+        assert_eq!(positions["a"], Position::new(2, 1));
+        // render time 1120 is just past (2,1)
+        let positions = buffer.positions(1000 + INTERPOLATION_DELAY_MS + 120);
+        assert_eq!(positions["a"], Position::new(2, 1));
+        // past the newest snapshot we hold the last known position
+        let positions = buffer.positions(5000);
+        assert_eq!(positions["a"], Position::new(3, 1));
+    }
+
+    #[test]
+    fn does_not_slide_across_jumps() {
+        let mut buffer = RemoteBuffer::default();
+        buffer.push("a", 1000, Position::new(1, 1), 1000);
+        buffer.push("a", 1100, Position::new(9, 9), 1100);
+        let positions = buffer.positions(1000 + INTERPOLATION_DELAY_MS + 90);
+        assert_eq!(positions["a"], Position::new(1, 1));
+    }
+}
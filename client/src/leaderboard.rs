@@ -0,0 +1,145 @@
+use mazeio_shared::mazeio_proto::game_client::GameClient;
+use mazeio_shared::{tonic, Leaderboard, LeaderboardKind, LeaderboardRequest};
+use std::time::Duration;
+
+use crossterm::event::{Event, KeyCode};
+use tui::{backend::Backend, Terminal};
+
+use super::config::{Action, Config};
+use super::ui::leaderboard_ui;
+
+/// entries asked for at once
+const PAGE_SIZE: u32 = 20;
+
+/// Which page of which leaderboard is being looked at
+#[derive(Debug)]
+pub struct LeaderboardView {
+    pub kind: LeaderboardKind,
+    pub offset: u32,
+    /// the page at offset, None until it arrived
+    pub page: Option<Leaderboard>,
+}
+
+impl LeaderboardView {
+    pub fn new() -> Self {
+        Self {
+            kind: LeaderboardKind::BestTimes,
+            offset: 0,
+            page: None,
+        }
+    }
+
+    /// Switch between best times and wins, starting at the top
+    pub fn toggle_kind(&mut self) {
+        self.kind = match self.kind {
+            LeaderboardKind::BestTimes => LeaderboardKind::Wins,
+            LeaderboardKind::Wins => LeaderboardKind::BestTimes,
+        };
+        self.offset = 0;
+        self.page = None;
+    }
+
+    pub fn next_page(&mut self) {
+        let total = self.page.as_ref().map(|page| page.total).unwrap_or(0);
+        if self.offset + PAGE_SIZE < total {
+            self.offset += PAGE_SIZE;
+            self.page = None;
+        }
+    }
+
+    pub fn previous_page(&mut self) {
+        if self.offset != 0 {
+            self.offset = self.offset.saturating_sub(PAGE_SIZE);
+            self.page = None;
+        }
+    }
+
+    fn request(&self) -> LeaderboardRequest {
+        LeaderboardRequest {
+            kind: self.kind.into(),
+            maze: None,
+            offset: self.offset,
+            limit: PAGE_SIZE,
+        }
+    }
+
+    /// "Page 2 of 3", counting an empty leaderboard as one page
+    pub fn page_status(&self) -> String {
+        let total = self.page.as_ref().map(|page| page.total).unwrap_or(0);
+        format!(
+            "Page {} of {}",
+            self.offset / PAGE_SIZE + 1,
+            total.div_ceil(PAGE_SIZE).max(1)
+        )
+    }
+}
+
+/// Browse the server's leaderboard until the user quits
+pub async fn run_leaderboard<B: Backend>(
+    client: &mut GameClient<tonic::transport::Channel>,
+    terminal: &mut Terminal<B>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut view = LeaderboardView::new();
+    loop {
+        if view.page.is_none() {
+            let page = client
+                .get_leaderboard(tonic::Request::new(view.request()))
+                .await?
+                .into_inner();
+            view.page = Some(page);
+        }
+        terminal.draw(|f| leaderboard_ui(&view, config, f))?;
+        if !crossterm::event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = crossterm::event::read()? {
+            if let Some(Action::Quit) = config.keys.get(&key.code) {
+                return Ok(());
+            }
+            match key.code {
+                KeyCode::Tab => view.toggle_kind(),
+                KeyCode::Left | KeyCode::PageUp => view.previous_page(),
+                KeyCode::Right | KeyCode::PageDown => view.next_page(),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_stay_within_the_total() {
+        let mut view = LeaderboardView::new();
+        view.page = Some(Leaderboard {
+            total: 45,
+            ..Leaderboard::default()
+        });
+        assert_eq!(view.page_status(), "Page 1 of 3");
+        view.previous_page();
+        assert_eq!(view.offset, 0);
+        assert!(view.page.is_some());
+
+        view.next_page();
+        view.page = Some(Leaderboard {
+            total: 45,
+            ..Leaderboard::default()
+        });
+        view.next_page();
+        assert_eq!(view.offset, 40);
+        view.page = Some(Leaderboard {
+            total: 45,
+            ..Leaderboard::default()
+        });
+        view.next_page();
+        assert_eq!(view.offset, 40);
+        assert_eq!(view.page_status(), "Page 3 of 3");
+
+        view.toggle_kind();
+        assert_eq!(view.kind, LeaderboardKind::Wins);
+        assert_eq!(view.offset, 0);
+    }
+}
@@ -4,119 +4,315 @@ use mazeio_shared::*;
 mod ui;
 use ui::*;
 
+mod assist;
+mod config;
+mod interpolation;
+mod leaderboard;
+mod minimap;
 mod model;
 use model::*;
+mod replay;
+mod trail;
+use trail::Trail;
 
+use config::{Action, Config, KeyBindings};
 use mazeio_proto::game_client::GameClient;
-use std::collections::HashSet;
 use tokio::sync::mpsc::Sender;
 // tui uses
 use crossterm::{
-    event::{Event, KeyCode},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEvent,
+        MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use std::io;
 
-async fn handle_event(
+/// Messages waiting to go out to the server before more are dropped
+const SEND_BUFFER: usize = 64;
+
+/// Send a message to the server without waiting, in the order messages are
+/// sent in so inputs reach the server in seq order. With the buffer full
+/// the message is dropped, and the server reconciles us once it catches up.
+/// A closed channel means the server ended the game, which the game stream
+/// tells us about.
+fn send(tx: &Sender<ClientMessage>, message: ClientMessage) {
+    let _ = tx.try_send(message);
+}
+
+/// Type into the chat message, with no key doing what it normally does
+fn handle_chat_key(code: KeyCode, tx: &Sender<ClientMessage>, state: &mut GameStateSynced) {
+    let input = match state.chat_input.as_mut() {
+        Some(input) => input,
+        None => return,
+    };
+    match code {
+        KeyCode::Char(c) if input.chars().count() < state.max_chat_len as usize => {
+            input.push(c);
+        }
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Esc => state.chat_input = None,
+        KeyCode::Enter => {
+            let text = state.chat_input.take().unwrap_or_default();
+            if !text.trim().is_empty() {
+                send(tx, ClientMessage::chat(text));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Keys and clicks, as opposed to the mouse moving about
+fn is_input(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Key(_)
+            | Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Down(_),
+                ..
+            })
+    )
+}
+
+fn handle_event(
     is_running: &mut bool,
     event: crossterm::event::Event,
-    tx: &Sender<InputDirection>,
-    pos_history: Rc<RefCell<HashSet<(u32, u32)>>>,
+    keys: &KeyBindings,
+    tx: &Sender<ClientMessage>,
+    trail: Rc<RefCell<Trail>>,
     game_state_synced: &mut GameStateSynced,
+    spectating: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut maybe_dir: Option<Direction> = None;
     match event {
+        Event::Key(key) if game_state_synced.chat_input.is_some() => {
+            handle_chat_key(key.code, tx, game_state_synced);
+        }
+        Event::Key(key) if spectating && key.code == KeyCode::Tab => {
+            game_state_synced.follow_next();
+        }
         Event::Key(key) => {
-            match key.code {
-                KeyCode::Char('a') => {
+            match keys.get(&key.code) {
+                Some(Action::MoveLeft) => {
                     maybe_dir = Some(Direction::Left);
                 }
-                KeyCode::Char('d') => {
+                Some(Action::MoveRight) => {
                     maybe_dir = Some(Direction::Right);
                 }
-                KeyCode::Char('w') => {
+                Some(Action::MoveUp) => {
                     maybe_dir = Some(Direction::Up);
                 }
-                KeyCode::Char('s') => {
+                Some(Action::MoveDown) => {
                     maybe_dir = Some(Direction::Down);
                 }
-                KeyCode::Esc => {
+                Some(Action::Chat) => {
+                    if !spectating && game_state_synced.max_chat_len != 0 {
+                        game_state_synced.chat_input = Some(String::new());
+                    }
+                }
+                Some(Action::Minimap) => {
+                    game_state_synced.show_minimap = !game_state_synced.show_minimap;
+                }
+                Some(Action::Trail) => {
+                    if let Ok(mut trail_mut) = trail.try_borrow_mut() {
+                        trail_mut.visible = !trail_mut.visible;
+                    }
+                }
+                Some(Action::Marker) if !spectating => {
+                    let player = game_state_synced
+                        .player_dict
+                        .get(&game_state_synced.player_id);
+                    if let (Some(pos), Ok(mut trail_mut)) =
+                        (player.and_then(|p| p.pos.as_ref()), trail.try_borrow_mut())
+                    {
+                        trail_mut.toggle_marker((pos.x, pos.y));
+                    }
+                }
+                Some(Action::Hint) => game_state_synced.assist.toggle_hint(),
+                Some(Action::Autopilot) => game_state_synced.assist.toggle_autopilot(),
+                Some(Action::Target) => {
+                    if let Ok(trail_inner) = trail.try_borrow() {
+                        game_state_synced.assist.next_target(&trail_inner.markers());
+                    }
+                }
+                Some(Action::Quit) => {
                     *is_running = false;
                 }
-                _ => {}
+                Some(Action::Marker) | Some(Action::None) | None => {}
             };
         }
+        Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            ..
+        }) => {
+            if let Some(cell) = game_state_synced.cell_at(column, row) {
+                if spectating {
+                    game_state_synced.look_at(cell);
+                } else {
+                    let player = game_state_synced
+                        .player_dict
+                        .get(&game_state_synced.player_id);
+                    if let Some(pos) = player.and_then(|p| p.pos.clone()) {
+                        let maze = &game_state_synced.maze;
+                        game_state_synced.assist.walk_to(maze, &pos, &cell);
+                    }
+                }
+            }
+        }
         Event::Resize(..) => {}
         _ => {}
     }
-    if let Some(dir) = maybe_dir {
-        // locally move the player (this will be overwritten at later syncing)
-        if let Some(player) = game_state_synced
+    if let (Some(dir), true) = (maybe_dir, spectating) {
+        game_state_synced.pan(dir);
+    } else if let Some(dir) = maybe_dir {
+        // taking over the controls stops the autopilot
+        game_state_synced.assist.stop();
+        step(dir, tx, &trail, game_state_synced);
+    }
+    Ok(())
+}
+
+/// Move our player, by key or autopilot
+fn step(
+    dir: Direction,
+    tx: &Sender<ClientMessage>,
+    trail: &Rc<RefCell<Trail>>,
+    game_state_synced: &mut GameStateSynced,
+) {
+    // locally predict the move (reconciled against the server at later syncing)
+    if let Some(input) = game_state_synced.apply_input(dir) {
+        let player = game_state_synced
             .player_dict
-            .get_mut(&game_state_synced.player_id)
-        {
-            (*player).move_if_valid(&game_state_synced.maze, dir);
-            if let Some(pos) = (*player).pos.clone() {
-                if let Ok(mut pos_history_mut) = pos_history.try_borrow_mut() {
-                    (*pos_history_mut).insert((pos.x, pos.y));
-                }
+            .get(&game_state_synced.player_id);
+        if let Some(pos) = player.and_then(|p| p.pos.clone()) {
+            if let Ok(mut trail_mut) = trail.try_borrow_mut() {
+                trail_mut.visit((pos.x, pos.y));
             }
-
-            // spawn a short thread to send the input to server
-            let tx_clone = tx.clone();
-            tokio::spawn(async move {
-                tx_clone
-                    .send(InputDirection {
-                        direction: dir.into(),
-                    })
-                    .await
-                    .unwrap();
-            });
         }
+
+        send(tx, ClientMessage::input(input));
     }
-    Ok(())
 }
 
 async fn run_app<B: Backend>(
-    mut game_state: GameState,
+    game_state: GameState,
     terminal: &mut Terminal<B>,
-    tx: &Sender<InputDirection>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut interval = tokio::time::interval(std::time::Duration::from_millis(100));
+    tx: &Sender<ClientMessage>,
+    config: &Config,
+    server_info: &ServerInfo,
+    trail: Rc<RefCell<Trail>>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(50));
     let mut is_running = true;
-    let game_state_synced = Rc::new(RefCell::new(game_state.to_synced().await));
-    let pos_history = Rc::new(RefCell::new(HashSet::with_capacity(100)));
+    let mut synced = game_state.to_synced().await;
+    if server_info.supports(features::CHAT) {
+        synced.max_chat_len = server_info.max_chat_len;
+    }
+    synced.assist.available = server_info.supports(features::ASSIST) && !config.spectate;
+    let game_state_synced = Rc::new(RefCell::new(synced));
     // let mut frame_num: u128 = 0;
     // let mut total_time: u128 = 0;
     while is_running {
         // frame_num += 1;
         //let now = Instant::now();
+        // the first key or click is acted on, the mouse moving about
+        // doesn't count so it can't crowd out keys
+        let mut handled = false;
         if let Ok(true) = crossterm::event::poll(std::time::Duration::from_millis(5)) {
             // println!("Got input!");
+            let event = crossterm::event::read()?;
+            handled = is_input(&event);
             if let Ok(mut state_synced_mut) = game_state_synced.try_borrow_mut() {
                 handle_event(
                     &mut is_running,
-                    crossterm::event::read()?,
+                    event,
+                    &config.keys,
                     tx,
-                    pos_history.clone(),
+                    trail.clone(),
                     &mut state_synced_mut,
-                )
-                .await?;
+                    config.spectate,
+                )?;
             }
         };
-        //clear keyboard buffer
+        //clear keyboard buffer, unless we are typing and want every key
         while crossterm::event::poll(std::time::Duration::from_millis(1))? {
-            crossterm::event::read()?;
+            let event = crossterm::event::read()?;
+            if let Ok(mut state_synced_mut) = game_state_synced.try_borrow_mut() {
+                if !handled && is_input(&event) {
+                    handled = true;
+                    handle_event(
+                        &mut is_running,
+                        event,
+                        &config.keys,
+                        tx,
+                        trail.clone(),
+                        &mut state_synced_mut,
+                        config.spectate,
+                    )?;
+                } else if let (Event::Key(key), true) =
+                    (event, state_synced_mut.chat_input.is_some())
+                {
+                    handle_chat_key(key.code, tx, &mut state_synced_mut);
+                }
+            }
+        }
+        // we are out of the game, so there is nothing left to play
+        if let Some(message) = game_state.disconnected().await {
+            return Ok(Some(message));
+        }
+        let remote_positions = game_state.remote_positions().await;
+        let rtt_ms = game_state.rtt_ms().await;
+        let ping_error = game_state.ping_error().await;
+        let idle_warning = game_state.take_idle_warning().await;
+        let spectators = game_state.spectators().await;
+        let chat_log = game_state.chat_log().await;
+        let scoreboard = game_state.scoreboard().await;
+        let revealed = game_state.take_revealed().await;
+        if let Ok(mut state_synced_mut) = game_state_synced.try_borrow_mut() {
+            for cells in revealed {
+                state_synced_mut.reveal(cells);
+            }
+            let pos = state_synced_mut
+                .player_dict
+                .get(&state_synced_mut.player_id)
+                .and_then(|player| player.pos.clone());
+            if let Some(pos) = pos {
+                let state = &mut *state_synced_mut;
+                let now = tokio::time::Instant::now();
+                if let Some(dir) = state.assist.autopilot_step(&state.maze, &pos, now) {
+                    step(dir, tx, &trail, state);
+                }
+            }
+            state_synced_mut.chat_log = chat_log;
+            state_synced_mut.scoreboard = scoreboard;
+            state_synced_mut.remote_positions = remote_positions;
+            state_synced_mut.rtt_ms = rtt_ms;
+            state_synced_mut.ping_error = ping_error;
+            state_synced_mut.spectators = spectators;
+            if let Some(seconds_left) = idle_warning {
+                state_synced_mut.idle_deadline = Some(
+                    tokio::time::Instant::now()
+                        + std::time::Duration::from_secs(seconds_left.into()),
+                );
+            }
         }
         //draw
-        terminal.draw(|f| ui(Some(game_state_synced.clone()), pos_history.clone(), f))?;
+        terminal.draw(|f| {
+            if config.spectate {
+                spectate_ui(game_state_synced.clone(), trail.clone(), config, f)
+            } else {
+                ui(Some(game_state_synced.clone()), trail.clone(), config, f)
+            }
+        })?;
 
-        let has_changed = game_state.changed_since_synced.lock().await;
-        if *has_changed == true {
-            drop(has_changed);
+        if let Some(players) = game_state.take_changed_players().await {
             if let Ok(mut state_synced_mut) = game_state_synced.try_borrow_mut() {
-                (*state_synced_mut).update_players(&mut game_state).await;
+                (*state_synced_mut).update_players(players);
             }
         }
 
@@ -124,32 +320,129 @@ async fn run_app<B: Backend>(
         //println!("Avg time: {:?}", total_time / frame_num);
         interval.tick().await;
     }
-    Ok(())
+    Ok(None)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = GameClient::connect("http://[::1]:50051").await?;
-    let game_state = GameState::initial_state("test-name".to_string(), &mut client).await?;
+    let config = Config::load()?;
+    if let Some(path) = &config.replay {
+        let replay = replay::Replay::read(path)?;
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        let result = replay::run_replay(replay, &mut terminal, &config).await;
+        disable_raw_mode()?;
+        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+        terminal.show_cursor()?;
+        return result;
+    }
+    let mut client = GameClient::connect(config.server.clone()).await?;
+    let server_info = client
+        .get_server_info(tonic::Request::new(ServerInfoRequest {}))
+        .await?
+        .into_inner();
+    if server_info.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Server speaks protocol version {} but this client speaks {}, please update",
+            server_info.protocol_version, PROTOCOL_VERSION
+        )
+        .into());
+    }
+    if config.leaderboard {
+        if !server_info.supports(features::LEADERBOARD) {
+            return Err("Server does not keep a leaderboard".into());
+        }
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        let result = leaderboard::run_leaderboard(&mut client, &mut terminal, &config).await;
+        disable_raw_mode()?;
+        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+        terminal.show_cursor()?;
+        return result;
+    }
+    if config.spectate && !server_info.supports(features::SPECTATE) {
+        return Err("Server does not support spectating".into());
+    }
+    let game_state =
+        match GameState::initial_state(config.name.clone(), config.spectate, &mut client).await {
+            Ok(game_state) => game_state,
+            Err(status)
+                if status.code() == tonic::Code::ResourceExhausted
+                    && !config.spectate
+                    && server_info.supports(features::JOIN_QUEUE) =>
+            {
+                GameState::wait_in_queue(config.name.clone(), &mut client).await?
+            }
+            // the only thing about joining the server can take issue with is the name
+            Err(status) if status.code() == tonic::Code::InvalidArgument => {
+                return Err(format!("{}, pick another one with --name", status.message()).into())
+            }
+            Err(status) => return Err(status.into()),
+        };
 
     // buffer to hold the direction values to be sent
-    let (tx, rx) = tokio::sync::mpsc::channel(5);
+    let (tx, rx) = tokio::sync::mpsc::channel(SEND_BUFFER);
     game_state.handle_player_stream(rx, &mut client).await?;
+    if server_info.supports(features::SCOREBOARD) {
+        game_state.load_scoreboard(&mut client).await?;
+    }
+    // spectators have no player to report a round trip time for
+    if server_info.supports(features::PING) && !config.spectate {
+        game_state.handle_ping(client.clone());
+    }
+
+    // spectators have no trail of their own to keep
+    let trail_path = match config.spectate {
+        true => None,
+        false => trail::path_for(
+            &game_state.maze.key(server_info.maze_algorithm()),
+            &server_info,
+        ),
+    };
+    let trail = match &trail_path {
+        Some(path) => Trail::load(path)?,
+        None => Trail::default(),
+    };
+    let trail = Rc::new(RefCell::new(trail));
 
-    // setup terminal
+    // setup terminal, with the mouse for clicking where to go
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // run app with UI
-    run_app(game_state, &mut terminal, &tx).await?;
+    let disconnected = run_app(
+        game_state,
+        &mut terminal,
+        &tx,
+        &config,
+        &server_info,
+        trail.clone(),
+    )
+    .await?;
 
     // restore terminal
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen,)?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
     terminal.show_cursor()?;
 
+    // so rejoining the same maze picks up where we left off
+    if let Some(path) = &trail_path {
+        trail.borrow().save(path)?;
+    }
+    if let Some(message) = disconnected {
+        println!("{}", message);
+    }
+
     Ok(())
 }
@@ -0,0 +1,133 @@
+use mazeio_shared::CellType;
+use tui::{buffer::Buffer, layout::Rect, style::Color, style::Style, widgets::Widget};
+
+use super::config::Theme;
+use super::model::GameStateSynced;
+use super::trail::Trail;
+
+/// The whole maze scaled down to fit, two maze rows per terminal row
+/// using half blocks, with players, the goal and our trail on it
+pub struct Minimap<'a> {
+    pub state: &'a GameStateSynced,
+    pub trail: &'a Trail,
+    pub theme: Theme,
+}
+
+/// How many maze cells one side of a minimap pixel covers, when
+/// fitting a maze into width columns and height rows
+fn scale(maze_width: u32, maze_height: u32, width: u16, height: u16) -> u32 {
+    let across = maze_width.div_ceil(width.max(1) as u32);
+    let down = maze_height.div_ceil(height.max(1) as u32 * 2);
+    across.max(down).max(1)
+}
+
+/// Rows needed to show a maze at the scale that fits it into width columns
+pub fn height_for(maze_width: u32, maze_height: u32, width: u16) -> u16 {
+    let scale = maze_width.div_ceil(width.max(1) as u32).max(1);
+    maze_height.div_ceil(scale * 2) as u16
+}
+
+impl Minimap<'_> {
+    /// Color of the pixel covering the scale by scale block at x, y.
+    /// Players show over the goal, which shows over the trail.
+    fn pixel(&self, x: u32, y: u32, scale: u32, players: &[(u32, u32, Color)]) -> Color {
+        let (end_x, end_y) = (
+            (x + scale).min(self.state.maze.width),
+            (y + scale).min(self.state.maze.height),
+        );
+        let covers = |cx: u32, cy: u32| (x..end_x).contains(&cx) && (y..end_y).contains(&cy);
+        if let Some((_, _, color)) = players.iter().find(|(px, py, _)| covers(*px, *py)) {
+            return *color;
+        }
+        if let Some(goal) = &self.state.maze.goal {
+            if covers(goal.x, goal.y) {
+                return self.theme.goal;
+            }
+        }
+        let (mut open, mut unknown) = (0, 0);
+        let mut visited = false;
+        for cy in y..end_y {
+            for cx in x..end_x {
+                match self.state.maze.get(cx as usize, cy as usize) {
+                    CellType::Open => open += 1,
+                    CellType::Unknown => unknown += 1,
+                    CellType::Wall => {}
+                }
+                visited |= self.trail.shows((cx, cy));
+            }
+        }
+        let cells = (end_x - x) * (end_y - y);
+        if visited {
+            self.theme.trail
+        } else if unknown * 2 > cells {
+            Color::Black
+        } else if open * 2 >= cells - unknown {
+            Color::Reset
+        } else {
+            Color::DarkGray
+        }
+    }
+}
+
+impl Widget for Minimap<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let maze = &self.state.maze;
+        if maze.width == 0 || maze.height == 0 {
+            return;
+        }
+        let scale = scale(maze.width, maze.height, area.width, area.height);
+        // ours first, so it shows over everyone else's
+        let mut players: Vec<(u32, u32, Color)> = self
+            .state
+            .player_dict
+            .values()
+            .filter_map(|player| {
+                let pos = self
+                    .state
+                    .remote_positions
+                    .get(&player.id)
+                    .or(player.pos.as_ref())?;
+                let color = self.state.color_of(&player.id, &self.theme);
+                Some((pos.x, pos.y, color))
+            })
+            .collect();
+        players.sort_by_key(|(_, _, color)| *color != self.theme.player);
+        for row in 0..area.height {
+            let y = row as u32 * scale * 2;
+            if y >= maze.height {
+                break;
+            }
+            for col in 0..area.width {
+                let x = col as u32 * scale;
+                if x >= maze.width {
+                    break;
+                }
+                let top = self.pixel(x, y, scale, &players);
+                let bottom = if y + scale < maze.height {
+                    self.pixel(x, y + scale, scale, &players)
+                } else {
+                    Color::Reset
+                };
+                buf.get_mut(area.x + col, area.y + row)
+                    .set_char('▀')
+                    .set_style(Style::default().fg(top).bg(bottom));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_mazes_are_scaled_to_fit() {
+        assert_eq!(scale(16, 32, 30, 20), 1);
+        assert_eq!(height_for(16, 32, 30), 16);
+        // too wide for 30 columns, so every pixel covers 2 by 2 cells
+        assert_eq!(scale(60, 20, 30, 20), 2);
+        assert_eq!(height_for(60, 20, 30), 5);
+        // too tall for the rows there are
+        assert_eq!(scale(10, 100, 30, 10), 5);
+    }
+}
@@ -1,10 +1,23 @@
 extern crate mazeio_shared;
 use mazeio_shared::*;
-use std::collections::HashMap;
+
+use super::assist::Assist;
+use super::config::Theme;
+use super::interpolation::RemoteBuffer;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
 use std::sync::Arc;
 use tokio::sync::{mpsc::Receiver, Mutex, RwLock};
+use tokio::time::Instant;
+use tui::{layout::Rect, style::Color};
 
 pub type AtomicPlayerDict = Arc<RwLock<HashMap<String, Player>>>;
+/// How often we measure our round trip time to the server
+const PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// How many cells the view moves per key press while spectating
+const PAN_STEP: u32 = 3;
+/// How many chat messages we hold on to
+const CHAT_HISTORY: usize = 50;
 use mazeio_proto::game_client::GameClient;
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use tonic::Request;
@@ -14,48 +27,374 @@ pub struct GameState {
     pub maze: ProtoMaze,
     pub player_dict: AtomicPlayerDict,
     pub changed_since_synced: Arc<Mutex<bool>>,
+    pub remote_buffer: Arc<Mutex<RemoteBuffer>>,
+    /// round trip time to the server, as measured by the last ping
+    pub rtt_ms: Arc<Mutex<u32>>,
+    /// why the last ping failed, None once one succeeds again
+    pub ping_error: Arc<Mutex<Option<String>>>,
+    /// seconds we had left when the server warned us about being idle
+    pub idle_warning: Arc<Mutex<Option<u32>>>,
+    /// why we are no longer in the game, once we aren't
+    pub disconnected: Arc<Mutex<Option<String>>>,
+    /// number of clients watching the game
+    pub spectators: Arc<Mutex<u32>>,
+    /// latest chat messages, oldest first
+    pub chat_log: Arc<Mutex<VecDeque<ChatMessage>>>,
+    /// stats of everyone in the game, by player id
+    pub scoreboard: Arc<Mutex<HashMap<String, PlayerStats>>>,
+    /// cells seen through the fog of war since the last sync
+    pub revealed: Arc<Mutex<Vec<RevealedCells>>>,
 }
 pub struct GameStateSynced {
     pub player_id: String,
     pub maze: ProtoMaze,
     pub player_dict: HashMap<String, Player>,
+    /// inputs sent to the server but not yet acknowledged by it
+    pub pending_inputs: VecDeque<InputDirection>,
+    pub next_seq: u32,
+    /// interpolated positions of other players to draw this frame
+    pub remote_positions: HashMap<String, Position>,
+    pub rtt_ms: u32,
+    pub ping_error: Option<String>,
+    /// when we will be kicked for being idle, if the server warned us
+    pub idle_deadline: Option<Instant>,
+    /// player the view follows while spectating or watching a replay,
+    /// never our own, since spectators have none
+    pub following: Option<String>,
+    /// where to look when neither playing nor following anyone
+    pub camera: Option<Position>,
+    pub spectators: u32,
+    pub chat_log: Vec<ChatMessage>,
+    /// the chat message being typed, while in chat mode
+    pub chat_input: Option<String>,
+    /// 0 when the server has chat turned off
+    pub max_chat_len: u32,
+    /// best first
+    pub scoreboard: Vec<PlayerStats>,
+    pub show_minimap: bool,
+    pub assist: Assist,
+    /// where on the terminal the maze was last drawn, for telling
+    /// which cell was clicked
+    pub view_area: Rect,
+    /// palette slot of every player, kept while they are in the game
+    /// so their color doesn't change when others come and go
+    color_slots: HashMap<String, usize>,
 }
 impl GameStateSynced {
-    pub async fn update_players(&mut self, game_state: &mut GameState) {
-        let player_lock = game_state.player_dict.read().await;
-        self.player_dict = (*player_lock).clone();
-        let mut changed_lock = game_state.changed_since_synced.lock().await;
-        *changed_lock = false;
+    pub fn new(player_id: String, maze: ProtoMaze, player_dict: HashMap<String, Player>) -> Self {
+        let mut state = Self {
+            player_id,
+            maze,
+            player_dict,
+            pending_inputs: VecDeque::new(),
+            next_seq: 1,
+            remote_positions: HashMap::new(),
+            rtt_ms: 0,
+            ping_error: None,
+            idle_deadline: None,
+            following: None,
+            camera: None,
+            spectators: 0,
+            chat_log: Vec::new(),
+            chat_input: None,
+            max_chat_len: 0,
+            scoreboard: Vec::new(),
+            show_minimap: true,
+            assist: Assist::default(),
+            view_area: Rect::default(),
+            color_slots: HashMap::new(),
+        };
+        state.assign_colors();
+        state
+    }
+
+    /// Give new opponents the least used palette slot, in id order so
+    /// everyone starting from the same players gets the same colors
+    fn assign_colors(&mut self) {
+        let player_dict = &self.player_dict;
+        self.color_slots
+            .retain(|id, _| player_dict.contains_key(id));
+        let mut new_ids: Vec<&String> = player_dict
+            .keys()
+            .filter(|id| **id != self.player_id && !self.color_slots.contains_key(*id))
+            .collect();
+        new_ids.sort();
+        for id in new_ids {
+            let mut uses: Vec<usize> = Vec::new();
+            for &slot in self.color_slots.values() {
+                if uses.len() <= slot {
+                    uses.resize(slot + 1, 0);
+                }
+                uses[slot] += 1;
+            }
+            // the first unused slot, or else the least used one
+            let slot = (0..=uses.len())
+                .min_by_key(|&slot| uses.get(slot).copied().unwrap_or(0))
+                .unwrap_or(0);
+            self.color_slots.insert(id.clone(), slot);
+        }
+    }
+
+    /// The color player id is drawn in, our own player standing out from the rest
+    pub fn color_of(&self, id: &str, theme: &Theme) -> Color {
+        if id == self.player_id {
+            return theme.player;
+        }
+        theme.opponent_color(self.color_slots.get(id).copied().unwrap_or(0))
+    }
+
+    /// The cell the view is centered on, our own player or the one we follow.
+    /// A followed player is someone else's, so their interpolated position is used.
+    pub fn view_center(&self) -> Position {
+        let id = self.following.as_ref().unwrap_or(&self.player_id);
+        self.player_dict
+            .get(id)
+            .and_then(|player| {
+                self.remote_positions
+                    .get(&player.id)
+                    .cloned()
+                    .or_else(|| player.pos.clone())
+            })
+            .or_else(|| self.camera.clone())
+            .unwrap_or_else(|| Position::new(self.maze.width / 2, self.maze.height / 2))
+    }
+
+    /// Spectators follow one player at a time,
+    /// cycling through players ordered by name
+    pub fn follow_next(&mut self) {
+        let mut players: Vec<&Player> = self.player_dict.values().collect();
+        players.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+        let current = players
+            .iter()
+            .position(|p| self.following.as_ref() == Some(&p.id));
+        let next = match current {
+            Some(i) => players.get(i + 1).or_else(|| players.first()),
+            None => players.first(),
+        };
+        if let Some(player) = next {
+            self.following = Some(player.id.clone());
+        }
+    }
+
+    /// Stop following anyone and move the view freely
+    pub fn pan(&mut self, dir: Direction) {
+        let center = self.view_center();
+        let (max_x, max_y) = (
+            self.maze.width.saturating_sub(1),
+            self.maze.height.saturating_sub(1),
+        );
+        let (x, y) = match dir {
+            Direction::Left => (center.x.saturating_sub(PAN_STEP), center.y),
+            Direction::Right => ((center.x + PAN_STEP).min(max_x), center.y),
+            Direction::Up => (center.x, center.y.saturating_sub(PAN_STEP)),
+            Direction::Down => (center.x, (center.y + PAN_STEP).min(max_y)),
+        };
+        self.look_at(Position::new(x, y));
+    }
+
+    /// Stop following anyone and center the view on pos
+    pub fn look_at(&mut self, pos: Position) {
+        self.following = None;
+        self.camera = Some(pos);
+    }
+
+    /// The maze cell drawn at column, row of the terminal, undoing the
+    /// scrolling and centering the view is drawn with
+    pub fn cell_at(&self, column: u16, row: u16) -> Option<Position> {
+        let area = self.view_area;
+        if !(area.x..area.x + area.width).contains(&column)
+            || !(area.y..area.y + area.height).contains(&row)
+        {
+            return None;
+        }
+        let center = self.view_center();
+        let x = column as i64 - area.x as i64 - (area.width / 2) as i64 + center.x as i64;
+        let y = row as i64 - area.y as i64 - (area.height / 2) as i64 + center.y as i64;
+        if x < 0 || y < 0 || x >= self.maze.width as i64 || y >= self.maze.height as i64 {
+            return None;
+        }
+        Some(Position::new(x as u32, y as u32))
+    }
+
+    /// Replace the player dict with the authoritative one from the server,
+    /// then replay our unacknowledged inputs on top of our own player
+    pub fn update_players(&mut self, players: HashMap<String, Player>) {
+        self.player_dict = players;
+        self.assign_colors();
+        if let Some(player) = self.player_dict.get_mut(&self.player_id) {
+            let acked_seq = player.last_input_seq;
+            while let Some(input) = self.pending_inputs.front() {
+                if input.seq > acked_seq {
+                    break;
+                }
+                self.pending_inputs.pop_front();
+            }
+            for input in self.pending_inputs.iter() {
+                if let Some(dir) = Direction::from_i32(input.direction) {
+                    player.move_if_valid(&self.maze, dir);
+                }
+            }
+        }
+    }
+
+    /// Fill in cells we saw through the fog of war
+    pub fn reveal(&mut self, revealed: RevealedCells) {
+        for cell in revealed.cells {
+            if cell.x < self.maze.width && cell.y < self.maze.height {
+                let cell_type = CellType::from_i32(cell.r#type).unwrap_or(CellType::Unknown);
+                self.maze.set(cell.x as usize, cell.y as usize, cell_type);
+            }
+        }
+        if revealed.goal.is_some() {
+            self.maze.goal = revealed.goal;
+        }
+    }
+
+    /// Locally predict a move for our player and record it as pending.
+    /// Returns the sequenced input to send to the server.
+    pub fn apply_input(&mut self, dir: Direction) -> Option<InputDirection> {
+        // any input resets the server's idle timer
+        self.idle_deadline = None;
+        let player = self.player_dict.get_mut(&self.player_id)?;
+        player.move_if_valid(&self.maze, dir);
+        let input = InputDirection {
+            direction: dir.into(),
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        self.pending_inputs.push_back(input.clone());
+        Some(input)
     }
 }
 
 impl GameState {
-    pub async fn to_synced(&mut self) -> GameStateSynced {
+    pub async fn to_synced(&self) -> GameStateSynced {
         let player_lock = self.player_dict.read().await;
         let mut changed_lock = self.changed_since_synced.lock().await;
         *changed_lock = false;
-        GameStateSynced {
-            player_id: self.player_id.clone(),
-            maze: self.maze.clone(),
-            player_dict: (*player_lock).clone(),
+        GameStateSynced::new(
+            self.player_id.clone(),
+            self.maze.clone(),
+            (*player_lock).clone(),
+        )
+    }
+    pub async fn rtt_ms(&self) -> u32 {
+        *self.rtt_ms.lock().await
+    }
+    pub async fn ping_error(&self) -> Option<String> {
+        self.ping_error.lock().await.clone()
+    }
+    pub async fn spectators(&self) -> u32 {
+        *self.spectators.lock().await
+    }
+    pub async fn chat_log(&self) -> Vec<ChatMessage> {
+        self.chat_log.lock().await.iter().cloned().collect()
+    }
+    /// Everyone's stats, best first
+    pub async fn scoreboard(&self) -> Vec<PlayerStats> {
+        let mut players: Vec<PlayerStats> =
+            self.scoreboard.lock().await.values().cloned().collect();
+        players.sort_by(PlayerStats::rank);
+        players
+    }
+    /// Get the stats so far, later changes arrive on the game stream
+    pub async fn load_scoreboard(
+        &self,
+        client: &mut GameClient<tonic::transport::Channel>,
+    ) -> Result<(), tonic::Status> {
+        let scoreboard = client
+            .get_scoreboard(Request::new(ScoreboardRequest {}))
+            .await?
+            .into_inner();
+        let mut scoreboard_lock = self.scoreboard.lock().await;
+        for stats in scoreboard.players {
+            // the stream may have been quicker
+            scoreboard_lock
+                .entry(stats.player_id.clone())
+                .or_insert(stats);
+        }
+        Ok(())
+    }
+    /// Cells seen through the fog of war since the last call
+    pub async fn take_revealed(&self) -> Vec<RevealedCells> {
+        std::mem::take(&mut *self.revealed.lock().await)
+    }
+    /// Seconds left before being kicked, if we were warned since the last call
+    pub async fn take_idle_warning(&self) -> Option<u32> {
+        self.idle_warning.lock().await.take()
+    }
+    pub async fn disconnected(&self) -> Option<String> {
+        self.disconnected.lock().await.clone()
+    }
+    /// Interpolated positions of remote players at the current time
+    pub async fn remote_positions(&self) -> HashMap<String, Position> {
+        let mut buffer_lock = self.remote_buffer.lock().await;
+        (*buffer_lock).positions(now_millis())
+    }
+    /// Get a copy of the players if they changed since the last call
+    pub async fn take_changed_players(&self) -> Option<HashMap<String, Player>> {
+        let mut changed_lock = self.changed_since_synced.lock().await;
+        if !*changed_lock {
+            return None;
         }
+        *changed_lock = false;
+        let player_lock = self.player_dict.read().await;
+        Some((*player_lock).clone())
     }
+    /// Join the game, or just watch it when spectating
     pub async fn initial_state(
         name: String,
+        spectate: bool,
         client: &mut GameClient<tonic::transport::Channel>,
     ) -> Result<Self, tonic::Status> {
         let join_game_response = client
-            .connect_player(Request::new(JoinGameRequest { name }))
+            .connect_player(Request::new(JoinGameRequest { name, spectate }))
+            .await?
+            .into_inner();
+        Self::from_join_response(join_game_response).map_err(tonic::Status::internal)
+    }
+
+    /// Wait in line for a full game, showing our place in it on stdout
+    pub async fn wait_in_queue(
+        name: String,
+        client: &mut GameClient<tonic::transport::Channel>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut queue_stream = client
+            .join_queue(Request::new(JoinGameRequest {
+                name,
+                spectate: false,
+            }))
             .await?
             .into_inner();
+        while let Some(queue_update) = queue_stream.next().await {
+            match queue_update?.update {
+                Some(queue_update::Update::Position(position)) => {
+                    print!(
+                        "\rGame is full, waiting in line: {} ahead of you ",
+                        position - 1
+                    );
+                    io::stdout().flush()?;
+                }
+                Some(queue_update::Update::Joined(join_game_response)) => {
+                    println!();
+                    return Ok(Self::from_join_response(join_game_response)?);
+                }
+                None => continue,
+            }
+        }
+        Err("Server closed the join queue".into())
+    }
 
+    /// Our view of the game we joined, which needs the maze to be in the response
+    fn from_join_response(join_game_response: JoinGameResponse) -> Result<Self, String> {
         match join_game_response {
             JoinGameResponse {
                 maze: Some(maze_val),
                 players,
                 player_id,
+                spectators,
             } => Ok(GameState {
-                player_id: player_id,
+                player_id,
                 maze: maze_val,
                 player_dict: Arc::new(RwLock::new(
                     players
@@ -64,14 +403,23 @@ impl GameState {
                         .collect::<HashMap<String, Player>>(),
                 )),
                 changed_since_synced: Arc::new(Mutex::new(false)),
+                remote_buffer: Arc::new(Mutex::new(RemoteBuffer::default())),
+                rtt_ms: Arc::new(Mutex::new(0)),
+                ping_error: Arc::new(Mutex::new(None)),
+                idle_warning: Arc::new(Mutex::new(None)),
+                disconnected: Arc::new(Mutex::new(None)),
+                spectators: Arc::new(Mutex::new(spectators)),
+                chat_log: Arc::new(Mutex::new(VecDeque::with_capacity(CHAT_HISTORY))),
+                scoreboard: Arc::new(Mutex::new(HashMap::new())),
+                revealed: Arc::new(Mutex::new(Vec::new())),
             }),
-            _ => panic!(),
+            _ => Err("Server let us into a game without a maze".to_string()),
         }
     }
 
     pub async fn handle_player_stream(
         &self,
-        rx: Receiver<InputDirection>,
+        rx: Receiver<ClientMessage>,
         client: &mut GameClient<tonic::transport::Channel>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let mut player_stream = client
@@ -81,25 +429,305 @@ impl GameState {
 
         let player_dict = self.player_dict.clone();
         let changed_since_synced = self.changed_since_synced.clone();
+        let remote_buffer = self.remote_buffer.clone();
+        let idle_warning = self.idle_warning.clone();
+        let disconnected = self.disconnected.clone();
+        let spectators = self.spectators.clone();
+        let chat_log = self.chat_log.clone();
+        let scoreboard = self.scoreboard.clone();
+        let revealed = self.revealed.clone();
+        let my_id = self.player_id.clone();
         tokio::spawn(async move {
             while let Some(res) = player_stream.next().await {
-                if let Ok(player) = res {
-                    let mut player_dict_lock = player_dict.write().await;
-                    if !player.alive {
-                        (*player_dict_lock).remove(&player.id);
-                    } else {
-                        (*player_dict_lock).insert(player.id.clone(), player);
-                        //println!("{:#?}\n", (*player_dict_lock));
+                if let Ok(GameUpdate {
+                    server_time_ms,
+                    update,
+                }) = res
+                {
+                    match update {
+                        Some(game_update::Update::Player(player)) => {
+                            if player.id != my_id {
+                                let mut buffer_lock = remote_buffer.lock().await;
+                                match (player.alive, player.pos.clone()) {
+                                    (true, Some(pos)) => (*buffer_lock).push(
+                                        &player.id,
+                                        server_time_ms,
+                                        pos,
+                                        now_millis(),
+                                    ),
+                                    // left the game, or out of sight in the fog of war
+                                    _ => (*buffer_lock).remove(&player.id),
+                                }
+                            } else if !player.alive {
+                                let mut disconnected_lock = disconnected.lock().await;
+                                *disconnected_lock =
+                                    Some(describe_disconnect(player.disconnect_reason()));
+                            }
+                            if !player.alive {
+                                scoreboard.lock().await.remove(&player.id);
+                            }
+                            let mut player_dict_lock = player_dict.write().await;
+                            if !player.alive {
+                                (*player_dict_lock).remove(&player.id);
+                            } else {
+                                (*player_dict_lock).insert(player.id.clone(), player);
+                                //println!("{:#?}\n", (*player_dict_lock));
+                            }
+                        }
+                        // we fell behind, so replace everything we know
+                        Some(game_update::Update::Snapshot(Snapshot { players })) => {
+                            let mut buffer_lock = remote_buffer.lock().await;
+                            (*buffer_lock)
+                                .retain(|id| players.iter().any(|p| p.id == id && p.pos.is_some()));
+                            for player in players.iter().filter(|p| p.id != my_id) {
+                                if let Some(pos) = player.pos.clone() {
+                                    (*buffer_lock).push(
+                                        &player.id,
+                                        server_time_ms,
+                                        pos,
+                                        now_millis(),
+                                    );
+                                }
+                            }
+                            scoreboard
+                                .lock()
+                                .await
+                                .retain(|id, _| players.iter().any(|p| &p.id == id));
+                            let mut player_dict_lock = player_dict.write().await;
+                            *player_dict_lock = players
+                                .into_iter()
+                                .map(|player| (player.id.clone(), player))
+                                .collect();
+                        }
+                        Some(game_update::Update::IdleWarning(warning)) => {
+                            if warning.player_id == my_id {
+                                let mut warning_lock = idle_warning.lock().await;
+                                *warning_lock = Some(warning.seconds_left);
+                            }
+                            continue;
+                        }
+                        Some(game_update::Update::Spectators(count)) => {
+                            *spectators.lock().await = count;
+                            continue;
+                        }
+                        Some(game_update::Update::Chat(message)) => {
+                            let mut chat_lock = chat_log.lock().await;
+                            if chat_lock.len() == CHAT_HISTORY {
+                                chat_lock.pop_front();
+                            }
+                            chat_lock.push_back(message);
+                            continue;
+                        }
+                        Some(game_update::Update::Stats(stats)) => {
+                            let mut scoreboard_lock = scoreboard.lock().await;
+                            scoreboard_lock.insert(stats.player_id.clone(), stats);
+                            continue;
+                        }
+                        Some(game_update::Update::Revealed(cells)) => {
+                            revealed.lock().await.push(cells);
+                            continue;
+                        }
+                        None => continue,
                     }
                     //println!("Got more player info from server!\n");
                     let mut changed_lock = changed_since_synced.lock().await;
                     *changed_lock = true;
-                } else {
-                    println! {"{:?}", res};
-                    break;
+                } else if let Err(status) = res {
+                    let mut disconnected_lock = disconnected.lock().await;
+                    disconnected_lock.get_or_insert_with(|| {
+                        format!("Lost connection to the server: {}", status.message())
+                    });
+                    return;
                 }
             }
+            let mut disconnected_lock = disconnected.lock().await;
+            disconnected_lock.get_or_insert_with(|| "The server closed the game".to_string());
         });
         Ok(())
     }
+
+    /// Periodically ping the server to measure our round trip time,
+    /// reporting the previous measurement so others can see it
+    pub fn handle_ping(&self, mut client: GameClient<tonic::transport::Channel>) {
+        let rtt_ms = self.rtt_ms.clone();
+        let ping_error = self.ping_error.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PING_INTERVAL);
+            loop {
+                interval.tick().await;
+                let last_rtt_ms = *rtt_ms.lock().await;
+                let res = client
+                    .ping(Request::new(PingRequest {
+                        client_time_ms: now_millis(),
+                        last_rtt_ms,
+                    }))
+                    .await;
+                match res {
+                    Ok(response) => {
+                        let sent = response.into_inner().client_time_ms;
+                        let mut rtt_lock = rtt_ms.lock().await;
+                        *rtt_lock = now_millis().saturating_sub(sent) as u32;
+                        *ping_error.lock().await = None;
+                    }
+                    // shown to the player, and tried again on the next tick
+                    Err(status) => {
+                        *ping_error.lock().await = Some(status.message().to_string());
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// What to tell the player when the server removed them
+fn describe_disconnect(reason: DisconnectReason) -> String {
+    match reason {
+        DisconnectReason::Quit => "You left the game",
+        DisconnectReason::Idle => "You were kicked for being idle",
+        DisconnectReason::Abuse => "You were kicked for sending too much input",
+        DisconnectReason::Shutdown => "The server shut down",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a single corridor along the middle row, walled in
+    fn corridor_maze() -> ProtoMaze {
+        let mut maze = ProtoMaze {
+            width: 7,
+            height: 3,
+            cells: vec![CellType::Wall as i32; 21],
+            goal: None,
+            seed: 0,
+        };
+        for x in 1..6 {
+            maze.set(x, 1, CellType::Open);
+        }
+        maze
+    }
+
+    fn synced_state() -> GameStateSynced {
+        let maze = corridor_maze();
+        let mut player = Player::new("me".to_string());
+        player.id = "me".to_string();
+        GameStateSynced::new(
+            player.id.clone(),
+            maze,
+            HashMap::from([(player.id.clone(), player)]),
+        )
+    }
+
+    #[test]
+    fn replays_unacknowledged_inputs() {
+        let mut state = synced_state();
+        for _ in 0..3 {
+            state.apply_input(Direction::Right).unwrap();
+        }
+        assert_eq!(state.player_dict["me"].pos, Some(Position::new(4, 1)));
+
+        // server has only processed the first input
+        let mut server_player = state.player_dict["me"].clone();
+        server_player.pos = Some(Position::new(2, 1));
+        server_player.last_input_seq = 1;
+        state.update_players(HashMap::from([("me".to_string(), server_player)]));
+
+        assert_eq!(state.pending_inputs.len(), 2);
+        assert_eq!(state.player_dict["me"].pos, Some(Position::new(4, 1)));
+    }
+
+    #[test]
+    fn authoritative_state_wins_once_acknowledged() {
+        let mut state = synced_state();
+        state.apply_input(Direction::Right).unwrap();
+
+        // server rejected the move (e.g. we were somewhere else)
+        let mut server_player = state.player_dict["me"].clone();
+        server_player.pos = Some(Position::new(1, 1));
+        server_player.last_input_seq = 1;
+        state.update_players(HashMap::from([("me".to_string(), server_player)]));
+
+        assert!(state.pending_inputs.is_empty());
+        assert_eq!(state.player_dict["me"].pos, Some(Position::new(1, 1)));
+    }
+
+    #[test]
+    fn spectators_follow_players_or_pan() {
+        let mut state = synced_state();
+        let mut other = Player::new("other".to_string());
+        other.pos = Some(Position::new(5, 1));
+        state.player_dict.insert(other.id.clone(), other.clone());
+        state.player_id.clear();
+        // nobody to look at yet, so the middle of the maze
+        assert_eq!(state.view_center(), Position::new(3, 1));
+
+        state.follow_next();
+        assert_eq!(state.following.as_deref(), Some("me"));
+        state.follow_next();
+        assert_eq!(state.following.as_ref(), Some(&other.id));
+        assert_eq!(state.view_center(), Position::new(5, 1));
+        // who we follow is still someone else to us
+        assert!(state.player_id.is_empty());
+        assert_ne!(
+            state.color_of(&other.id, &Theme::default()),
+            Theme::default().player
+        );
+
+        // panning starts from whoever we were following, and stays in the maze
+        state.pan(Direction::Right);
+        assert_eq!(state.following, None);
+        assert_eq!(state.view_center(), Position::new(6, 1));
+        state.pan(Direction::Left);
+        assert_eq!(state.view_center(), Position::new(3, 1));
+    }
+
+    #[test]
+    fn joining_without_a_maze_is_an_error() {
+        assert!(GameState::from_join_response(JoinGameResponse::default()).is_err());
+    }
+
+    #[test]
+    fn clicks_map_back_to_maze_cells() {
+        let mut state = synced_state();
+        state.view_area = Rect::new(2, 3, 9, 5);
+        // we are at 1,1 in the middle of the view, at column 6 and row 5
+        assert_eq!(state.cell_at(6, 5), Some(Position::new(1, 1)));
+        assert_eq!(state.cell_at(9, 5), Some(Position::new(4, 1)));
+        assert_eq!(state.cell_at(5, 4), Some(Position::new(0, 0)));
+        // left of the maze, and left of the view
+        assert_eq!(state.cell_at(4, 5), None);
+        assert_eq!(state.cell_at(1, 5), None);
+    }
+
+    #[test]
+    fn opponents_keep_distinct_colors() {
+        let mut state = synced_state();
+        let theme = Theme::default();
+        let mut players = state.player_dict.clone();
+        for id in ["a", "b", "c"] {
+            let mut player = Player::new(id.to_string());
+            player.id = id.to_string();
+            players.insert(id.to_string(), player);
+        }
+        state.update_players(players.clone());
+        assert_eq!(state.color_of("me", &theme), theme.player);
+        assert_eq!(state.color_of("a", &theme), theme.opponent);
+        let colors: Vec<Color> = ["a", "b", "c"]
+            .iter()
+            .map(|id| state.color_of(id, &theme))
+            .collect();
+        assert!(colors[0] != colors[1] && colors[1] != colors[2] && colors[0] != colors[2]);
+
+        // b leaving doesn't recolor c, and the next one to come gets b's color
+        let c_color = state.color_of("c", &theme);
+        let b_color = state.color_of("b", &theme);
+        let mut d = players.remove("b").unwrap();
+        d.id = "d".to_string();
+        players.insert("d".to_string(), d);
+        state.update_players(players);
+        assert_eq!(state.color_of("c", &theme), c_color);
+        assert_eq!(state.color_of("d", &theme), b_color);
+    }
 }
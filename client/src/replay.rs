@@ -0,0 +1,320 @@
+use mazeio_shared::{Player, ReplayFrame, ReplayHeader};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+use crossterm::event::{Event, KeyCode};
+use tui::{backend::Backend, Terminal};
+
+use super::config::{Action, Config};
+use super::model::GameStateSynced;
+use super::trail::Trail;
+use super::ui::{replay_ui, Rc, RefCell};
+
+const MIN_SPEED: f64 = 0.25;
+const MAX_SPEED: f64 = 8.0;
+/// how far the arrow keys jump
+const SEEK_MS: i64 = 5000;
+
+/// A recorded game being played back
+#[derive(Debug)]
+pub struct Replay {
+    header: ReplayHeader,
+    frames: Vec<ReplayFrame>,
+    /// number of frames applied to `players`
+    applied: usize,
+    players: HashMap<String, Player>,
+    /// playback position, relative to the start of the recording
+    time_ms: u64,
+    speed: f64,
+    paused: bool,
+    /// id of the player the view is centered on
+    following: Option<String>,
+    /// cells the followed player has been on so far
+    trail: HashSet<(u32, u32)>,
+    show_minimap: bool,
+}
+
+impl Replay {
+    pub fn new(header: ReplayHeader, frames: Vec<ReplayFrame>) -> Self {
+        let mut replay = Self {
+            header,
+            frames,
+            applied: 0,
+            players: HashMap::new(),
+            time_ms: 0,
+            speed: 1.0,
+            paused: false,
+            following: None,
+            trail: HashSet::new(),
+            show_minimap: true,
+        };
+        replay.sync();
+        replay
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let bytes = std::fs::read(path)?;
+        let (header, frames) = mazeio_shared::replay::read(&bytes)?;
+        if header.maze.is_none() {
+            return Err(format!("{} has no maze in it", path.display()).into());
+        }
+        Ok(Self::new(header, frames))
+    }
+
+    fn frame_time_ms(&self, frame: &ReplayFrame) -> u64 {
+        frame
+            .server_time_ms
+            .saturating_sub(self.header.started_at_ms)
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.frames
+            .last()
+            .map(|frame| self.frame_time_ms(frame))
+            .unwrap_or(0)
+    }
+
+    /// Move playback along by the wall clock time that passed
+    pub fn advance(&mut self, elapsed: Duration) {
+        if self.paused {
+            return;
+        }
+        let step = (elapsed.as_millis() as f64 * self.speed) as u64;
+        self.time_ms = (self.time_ms + step).min(self.duration_ms());
+        self.sync();
+    }
+
+    /// Jump forwards or backwards in the recording
+    pub fn seek(&mut self, delta_ms: i64) {
+        let target = self.time_ms as i64 + delta_ms;
+        self.time_ms = (target.max(0) as u64).min(self.duration_ms());
+        self.sync();
+    }
+
+    /// Apply every frame up to the playback position. Frames only
+    /// hold what changed, so going backwards replays from the start.
+    fn sync(&mut self) {
+        let rewound =
+            self.applied > 0 && self.frame_time_ms(&self.frames[self.applied - 1]) > self.time_ms;
+        if rewound {
+            self.applied = 0;
+            self.players.clear();
+            self.trail.clear();
+        }
+        while let Some(frame) = self.frames.get(self.applied) {
+            if self.frame_time_ms(frame) > self.time_ms {
+                break;
+            }
+            for player in &frame.players {
+                if player.alive {
+                    self.players.insert(player.id.clone(), player.clone());
+                } else {
+                    self.players.remove(&player.id);
+                }
+            }
+            self.applied += 1;
+            self.extend_trail();
+        }
+        let lost = match &self.following {
+            Some(id) => !self.players.contains_key(id),
+            None => true,
+        };
+        if lost {
+            self.following = self.players.keys().min().cloned();
+            self.rebuild_trail();
+        }
+    }
+
+    fn extend_trail(&mut self) {
+        let pos = self
+            .following
+            .as_ref()
+            .and_then(|id| self.players.get(id))
+            .and_then(|player| player.pos.as_ref());
+        if let Some(pos) = pos {
+            self.trail.insert((pos.x, pos.y));
+        }
+    }
+
+    fn rebuild_trail(&mut self) {
+        self.trail.clear();
+        let id = match &self.following {
+            Some(id) => id,
+            None => return,
+        };
+        for frame in &self.frames[..self.applied] {
+            let pos = frame
+                .players
+                .iter()
+                .find(|player| &player.id == id)
+                .and_then(|player| player.pos.as_ref());
+            if let Some(pos) = pos {
+                self.trail.insert((pos.x, pos.y));
+            }
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2.0).min(MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2.0).max(MIN_SPEED);
+    }
+
+    /// Center the view on the next player, ordered by id
+    pub fn follow_next(&mut self) {
+        let mut ids: Vec<&String> = self.players.keys().collect();
+        ids.sort();
+        let next = match &self.following {
+            Some(current) => ids.iter().find(|id| **id > current).or_else(|| ids.first()),
+            None => ids.first(),
+        };
+        self.following = next.map(|id| id.to_string());
+        self.rebuild_trail();
+    }
+
+    pub fn toggle_minimap(&mut self) {
+        self.show_minimap = !self.show_minimap;
+    }
+
+    pub fn to_synced(&self) -> GameStateSynced {
+        let mut synced = GameStateSynced::new(
+            String::new(),
+            self.header.maze.clone().unwrap_or_default(),
+            self.players.clone(),
+        );
+        synced.following = self.following.clone();
+        synced.show_minimap = self.show_minimap;
+        synced
+    }
+
+    pub fn trail(&self) -> HashSet<(u32, u32)> {
+        self.trail.clone()
+    }
+
+    pub fn status(&self) -> String {
+        let following = self
+            .following
+            .as_ref()
+            .and_then(|id| self.players.get(id))
+            .map(|player| format!(", following {}", player.name))
+            .unwrap_or_default();
+        format!(
+            "Replay {:.1}s / {:.1}s at {}x{}{}",
+            self.time_ms as f64 / 1000.0,
+            self.duration_ms() as f64 / 1000.0,
+            self.speed,
+            if self.paused { " (paused)" } else { "" },
+            following,
+        )
+    }
+}
+
+/// Play a replay in the terminal until the user quits
+pub async fn run_replay<B: Backend>(
+    mut replay: Replay,
+    terminal: &mut Terminal<B>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tick = Duration::from_millis(50);
+    let mut interval = mazeio_shared::tokio::time::interval(tick);
+    loop {
+        while crossterm::event::poll(Duration::from_millis(0))? {
+            if let Event::Key(key) = crossterm::event::read()? {
+                match config.keys.get(&key.code) {
+                    Some(Action::Quit) => return Ok(()),
+                    Some(Action::Minimap) => replay.toggle_minimap(),
+                    _ => {}
+                }
+                match key.code {
+                    KeyCode::Char(' ') => replay.toggle_pause(),
+                    KeyCode::Left => replay.seek(-SEEK_MS),
+                    KeyCode::Right => replay.seek(SEEK_MS),
+                    KeyCode::Char('+') | KeyCode::Char('=') => replay.faster(),
+                    KeyCode::Char('-') => replay.slower(),
+                    KeyCode::Tab => replay.follow_next(),
+                    _ => {}
+                }
+            }
+        }
+        let state = Rc::new(RefCell::new(replay.to_synced()));
+        let trail = Rc::new(RefCell::new(Trail::from_cells(replay.trail())));
+        let status = replay.status();
+        terminal.draw(|f| replay_ui(state, trail, status, config, f))?;
+        interval.tick().await;
+        replay.advance(tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mazeio_shared::{Position, ProtoMaze};
+
+    fn player(id: &str, x: u32, alive: bool) -> Player {
+        Player {
+            id: id.to_string(),
+            name: id.to_string(),
+            pos: Some(Position { x, y: 0 }),
+            alive,
+            ..Default::default()
+        }
+    }
+
+    fn frame(time_ms: u64, players: Vec<Player>) -> ReplayFrame {
+        ReplayFrame {
+            tick: time_ms / 100,
+            server_time_ms: 1000 + time_ms,
+            inputs: vec![],
+            players,
+        }
+    }
+
+    fn replay() -> Replay {
+        let header = ReplayHeader {
+            maze: Some(ProtoMaze::default()),
+            started_at_ms: 1000,
+            tick_rate: 10,
+            ..Default::default()
+        };
+        Replay::new(
+            header,
+            vec![
+                frame(0, vec![player("a", 0, true)]),
+                frame(1000, vec![player("a", 1, true), player("b", 5, true)]),
+                frame(2000, vec![player("a", 2, false)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn seeking_back_rebuilds_players() {
+        let mut replay = replay();
+        assert_eq!(replay.duration_ms(), 2000);
+        replay.seek(1500);
+        assert_eq!(replay.players.len(), 2);
+        assert_eq!(replay.players["a"].pos, Some(Position { x: 1, y: 0 }));
+        assert_eq!(replay.trail(), HashSet::from([(0, 0), (1, 0)]));
+
+        replay.seek(-1000);
+        assert_eq!(replay.players.len(), 1);
+        assert_eq!(replay.players["a"].pos, Some(Position { x: 0, y: 0 }));
+        assert_eq!(replay.trail(), HashSet::from([(0, 0)]));
+    }
+
+    #[test]
+    fn dead_players_are_removed_and_unfollowed() {
+        let mut replay = replay();
+        assert_eq!(replay.following.as_deref(), Some("a"));
+        replay.seek(5000);
+        assert!(!replay.players.contains_key("a"));
+        assert_eq!(replay.following.as_deref(), Some("b"));
+        assert_eq!(replay.trail(), HashSet::from([(5, 0)]));
+    }
+}
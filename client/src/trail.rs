@@ -0,0 +1,190 @@
+use mazeio_shared::{features, MazeKey, ServerInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Where we have been in a maze and how often, plus the markers we dropped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trail {
+    /// times each cell was entered
+    visits: HashMap<(u32, u32), u32>,
+    markers: HashSet<(u32, u32)>,
+    /// whether the visited cells are drawn, markers always are
+    pub visible: bool,
+}
+
+/// How a trail is kept on disk
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct TrailFile {
+    /// x, y and the number of visits
+    visits: Vec<(u32, u32, u32)>,
+    markers: Vec<(u32, u32)>,
+}
+
+impl Default for Trail {
+    fn default() -> Self {
+        Self {
+            visits: HashMap::new(),
+            markers: HashSet::new(),
+            visible: true,
+        }
+    }
+}
+
+/// Where the trail for maze on the server described by info is saved. With
+/// fog of war the seed is hidden, so the maze goes by the id the server gave
+/// it instead, and there is nowhere to save to if the server gave none.
+pub fn path_for(maze: &MazeKey, info: &ServerInfo) -> Option<PathBuf> {
+    let name = if info.supports(features::FOG) {
+        // it ends up in a path, so anything but a plain id is refused
+        let id = &info.maze_id;
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return None;
+        }
+        format!("fog-{}.toml", id)
+    } else {
+        format!(
+            "{}x{}-{}-{}.toml",
+            maze.width, maze.height, maze.maze_algorithm, maze.seed
+        )
+    };
+    dirs::data_dir().map(|dir| dir.join("mazeio").join("trails").join(name))
+}
+
+impl Trail {
+    /// Cells visited once each, as when following someone in a replay
+    pub fn from_cells(cells: HashSet<(u32, u32)>) -> Self {
+        Self {
+            visits: cells.into_iter().map(|cell| (cell, 1)).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// The trail saved at path, or an empty one if nothing was saved yet
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("Could not read trail {}: {}", path.display(), e).into()),
+        };
+        let file: TrailFile = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid trail file {}: {}", path.display(), e))?;
+        Ok(Self {
+            visits: file
+                .visits
+                .into_iter()
+                .map(|(x, y, count)| ((x, y), count))
+                .collect(),
+            markers: file.markers.into_iter().collect(),
+            ..Self::default()
+        })
+    }
+
+    /// Write the trail to path, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut visits: Vec<(u32, u32, u32)> = self
+            .visits
+            .iter()
+            .map(|(&(x, y), &count)| (x, y, count))
+            .collect();
+        visits.sort_unstable();
+        let mut markers: Vec<(u32, u32)> = self.markers.iter().copied().collect();
+        markers.sort_unstable();
+        let contents = toml::to_string(&TrailFile { visits, markers })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Could not save trail {}: {}", path.display(), e).into())
+    }
+
+    /// We stepped onto cell
+    pub fn visit(&mut self, cell: (u32, u32)) {
+        *self.visits.entry(cell).or_insert(0) += 1;
+    }
+
+    pub fn visits(&self, cell: (u32, u32)) -> u32 {
+        self.visits.get(&cell).copied().unwrap_or(0)
+    }
+
+    /// Whether cell is drawn as part of the trail
+    pub fn shows(&self, cell: (u32, u32)) -> bool {
+        self.visible && self.visits.contains_key(&cell)
+    }
+
+    /// Drop a marker on cell, or pick up the one already there
+    pub fn toggle_marker(&mut self, cell: (u32, u32)) {
+        if !self.markers.remove(&cell) {
+            self.markers.insert(cell);
+        }
+    }
+
+    pub fn has_marker(&self, cell: (u32, u32)) -> bool {
+        self.markers.contains(&cell)
+    }
+
+    /// Every marker, top to bottom and left to right
+    pub fn markers(&self) -> Vec<(u32, u32)> {
+        let mut markers: Vec<(u32, u32)> = self.markers.iter().copied().collect();
+        markers.sort_unstable_by_key(|&(x, y)| (y, x));
+        markers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visits_and_markers_survive_a_save() {
+        let mut trail = Trail::default();
+        trail.visit((1, 1));
+        trail.visit((2, 1));
+        trail.visit((1, 1));
+        trail.toggle_marker((2, 1));
+        trail.toggle_marker((3, 1));
+        trail.toggle_marker((3, 1));
+        assert_eq!(trail.visits((1, 1)), 2);
+        assert!(trail.has_marker((2, 1)));
+        assert!(!trail.has_marker((3, 1)));
+        trail.visible = false;
+        assert!(!trail.shows((1, 1)));
+
+        let path = std::env::temp_dir()
+            .join(format!("mazeio-trail-{}", std::process::id()))
+            .join("trail.toml");
+        assert_eq!(Trail::load(&path).unwrap(), Trail::default());
+        trail.save(&path).unwrap();
+        let loaded = Trail::load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(loaded.visits((1, 1)), 2);
+        assert_eq!(loaded.visits((2, 1)), 1);
+        assert!(loaded.has_marker((2, 1)));
+        assert!(loaded.visible);
+
+        let maze = MazeKey {
+            width: 10,
+            height: 10,
+            seed: 0,
+            ..MazeKey::default()
+        };
+        let mut info = ServerInfo::default();
+        if let Some(path) = path_for(&maze, &info) {
+            let other = MazeKey {
+                maze_algorithm: 1,
+                ..maze.clone()
+            };
+            assert_ne!(path_for(&other, &info), Some(path));
+        }
+        // in the fog, only by an id the server gave the maze
+        info.features.push(features::FOG.to_string());
+        assert!(path_for(&maze, &info).is_none());
+        info.maze_id = "../../elsewhere".to_string();
+        assert!(path_for(&maze, &info).is_none());
+        info.maze_id = "1b4e28ba-2fa1-11d2-883f-0016d3cca427".to_string();
+        if let Some(path) = path_for(&maze, &info) {
+            assert!(path.ends_with("fog-1b4e28ba-2fa1-11d2-883f-0016d3cca427.toml"));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use mazeio_shared::tokio::time::Instant;
 use mazeio_shared::{CellType, Position};
 use tui::{buffer::Buffer, layout::Rect};
 
@@ -5,185 +6,713 @@ pub use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Layout},
     style::{Color, Modifier, Style},
-    text::{Span, Spans, Text},
+    text::{Span, Spans},
     widgets::{Block, Borders, List, ListItem, Paragraph, Widget, Wrap},
     Frame, Terminal,
 };
 pub use unicode_width::UnicodeWidthStr;
 
+use super::config::{Action, Config, Theme};
+use super::leaderboard::LeaderboardView;
+use super::minimap::{self, Minimap};
+use mazeio_shared::LeaderboardKind;
+
+/// Height of the chat panel, borders included
+const CHAT_HEIGHT: u16 = 8;
+/// Height of the instructions panel, borders included
+const INSTRUCTIONS_HEIGHT: u16 = 4;
+/// Width of the player list and scoreboard, borders included
+const SIDEBAR_WIDTH: u16 = 32;
+/// Most the minimap grows to, borders included, shrinking the maze to fit
+const MINIMAP_MAX_HEIGHT: u16 = 18;
 use super::model::GameStateSynced;
+use super::trail::Trail;
 pub use core::cell::RefCell;
 use std::collections::HashSet;
 pub use std::rc::Rc;
 
 pub struct GameView {
     state: Option<Rc<RefCell<GameStateSynced>>>,
-    pos_history: Rc<RefCell<HashSet<(u32, u32)>>>,
+    trail: Rc<RefCell<Trail>>,
+    theme: Theme,
+}
+
+/// Arrow pointing from inside the viewport towards a cell dx, dy past its edge
+fn edge_arrow(dx: i32, dy: i32) -> char {
+    match (dx.signum(), dy.signum()) {
+        (-1, -1) => '↖',
+        (1, -1) => '↗',
+        (-1, 1) => '↙',
+        (1, 1) => '↘',
+        (-1, _) => '←',
+        (1, _) => '→',
+        (_, -1) => '↑',
+        _ => '↓',
+    }
 }
 
+/// Draw ch at pos. Off-screen, nothing is drawn, unless point_offscreen
+/// is set, in which case an arrow at the edge of the view points to pos.
+#[allow(clippy::too_many_arguments)]
 fn draw_player(
-    pos_history: Rc<RefCell<HashSet<(u32, u32)>>>,
+    trail: Rc<RefCell<Trail>>,
+    theme: &Theme,
     pos: Position,
     ch: char,
     fg_col: Color,
+    point_offscreen: bool,
     scroll: &(u32, u32),
     centering: &(u16, u16),
     area: &Rect,
     buf: &mut Buffer,
 ) {
+    if area.width == 0 || area.height == 0 {
+        return;
+    }
     let x = pos.x as i32 - scroll.0 as i32 + area.x as i32 + centering.0 as i32;
     let y = pos.y as i32 - scroll.1 as i32 + area.y as i32 + centering.1 as i32;
-    if x >= area.x as i32
-        && x < (area.x + area.width) as i32
-        && y >= area.y as i32
-        && y < (area.y + area.height) as i32
-    {
-        let cell = buf.get_mut(x.try_into().unwrap(), y.try_into().unwrap());
-        let mut style = Style::default().fg(fg_col);
-        if let Ok(pos_history_inner) = pos_history.try_borrow_mut() {
-            if (*pos_history_inner).contains(&(pos.x, pos.y)) {
-                style = style.bg(Color::Blue);
-            }
+    let edge_x = x.clamp(area.x as i32, (area.x + area.width) as i32 - 1);
+    let edge_y = y.clamp(area.y as i32, (area.y + area.height) as i32 - 1);
+    if (edge_x, edge_y) != (x, y) {
+        if point_offscreen {
+            let cell = buf.get_mut(edge_x as u16, edge_y as u16);
+            cell.set_char(edge_arrow(x - edge_x, y - edge_y))
+                .set_style(Style::default().fg(fg_col).add_modifier(Modifier::BOLD));
         }
-        cell.set_char(ch).set_style(style);
+        return;
     }
+    let cell = buf.get_mut(x as u16, y as u16);
+    let mut style = Style::default().fg(fg_col);
+    if let Ok(trail_inner) = trail.try_borrow_mut() {
+        if trail_inner.shows((pos.x, pos.y)) {
+            style = style.bg(theme.trail);
+        }
+    }
+    cell.set_char(ch).set_style(style);
 }
 
 impl Widget for GameView {
     fn render(self, area: Rect, buf: &mut Buffer) {
         // if state is not null
-        match self {
-            GameView {
-                state: Some(state_ref),
-                pos_history,
-            } => {
-                let state = state_ref.borrow();
-                let player_pos = state.player_dict[&state.player_id].pos.clone().unwrap();
-                let scroll = (player_pos.x, player_pos.y);
-                let centering = (area.width / 2, area.height / 2);
-
-                for i in area.y..area.y + area.height {
-                    for j in area.x..area.x + area.width {
-                        let x = j as i32 + scroll.0 as i32 - area.x as i32 - centering.0 as i32;
-                        let y = i as i32 + scroll.1 as i32 - area.y as i32 - centering.1 as i32;
-                        if x >= 0
-                            && x < state.maze.width as i32
-                            && y >= 0
-                            && y < state.maze.height as i32
-                        {
-                            let cell = buf.get_mut(j.try_into().unwrap(), i.try_into().unwrap());
-                            let mut style = Style::default();
-                            if let Ok(pos_history_inner) = pos_history.try_borrow_mut() {
-                                if (*pos_history_inner).contains(&(x as u32, y as u32)) {
-                                    style = style.bg(Color::Blue);
+        if let GameView {
+            state: Some(state_ref),
+            trail,
+            theme,
+        } = self
+        {
+            let state = state_ref.borrow();
+            let center = state.view_center();
+            let player_pos = state
+                .player_dict
+                .get(&state.player_id)
+                .map(|_| center.clone());
+            let scroll = (center.x, center.y);
+            let centering = (area.width / 2, area.height / 2);
+            // the way to the goal or target, when hints are on
+            let route: HashSet<(u32, u32)> = state
+                .player_dict
+                .get(&state.player_id)
+                .and_then(|player| player.pos.as_ref())
+                .and_then(|pos| state.assist.route(&state.maze, pos))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|pos| (pos.x, pos.y))
+                .collect();
+
+            for i in area.y..area.y + area.height {
+                for j in area.x..area.x + area.width {
+                    let x = j as i32 + scroll.0 as i32 - area.x as i32 - centering.0 as i32;
+                    let y = i as i32 + scroll.1 as i32 - area.y as i32 - centering.1 as i32;
+                    if x >= 0
+                        && x < state.maze.width as i32
+                        && y >= 0
+                        && y < state.maze.height as i32
+                    {
+                        let cell = buf.get_mut(j, i);
+                        let cell_type = CellType::from_i32(
+                            state.maze.cells[(y * state.maze.width as i32 + x) as usize],
+                        )
+                        .unwrap_or(CellType::Open);
+                        // unexplored parts of the maze stay dark in the fog of war
+                        let mut style = match cell_type {
+                            CellType::Unknown => Style::default().fg(Color::DarkGray),
+                            _ => Style::default(),
+                        };
+                        let mut ch = cell_type.to_char();
+                        if let Ok(trail_inner) = trail.try_borrow_mut() {
+                            let cell_pos = (x as u32, y as u32);
+                            if trail_inner.shows(cell_pos) {
+                                style = style.bg(theme.trail);
+                                // Trémaux style, a cell entered twice leads nowhere new
+                                if trail_inner.visits(cell_pos) >= 2 {
+                                    ch = '×';
                                 }
                             }
-                            cell.set_char(
-                                CellType::from_i32(
-                                    state.maze.cells[(y * state.maze.width as i32 + x) as usize],
-                                )
-                                .unwrap_or(CellType::Open)
-                                .to_char(),
-                            )
-                            .set_style(style);
+                            if route.contains(&cell_pos) {
+                                ch = '·';
+                                style = style.fg(theme.hint).add_modifier(Modifier::BOLD);
+                            }
+                            if trail_inner.has_marker(cell_pos) {
+                                ch = '◆';
+                                style = style.fg(theme.accent);
+                            }
                         }
+                        cell.set_char(ch).set_style(style);
                     }
                 }
+            }
 
-                // draw opponents
-                for (_id, player) in state.player_dict.clone().iter() {
-                    let pos = (*player).pos.clone().unwrap();
-                    draw_player(
-                        pos_history.clone(),
-                        pos,
-                        '●',
-                        Color::Red,
-                        &scroll.clone(),
-                        &centering,
-                        &area,
-                        buf,
-                    );
-                }
+            // draw the goal, if the game mode has one
+            if let Some(goal) = state.maze.goal.clone() {
+                draw_player(
+                    trail.clone(),
+                    &theme,
+                    goal,
+                    '⚑',
+                    theme.goal,
+                    false,
+                    &scroll,
+                    &centering,
+                    &area,
+                    buf,
+                );
+            }
+
+            // draw opponents, interpolated if we have buffered states for them,
+            // with arrows pointing at the ones out of view. Opponents
+            // without a position are hidden by the fog of war.
+            for (id, player) in state.player_dict.clone().iter() {
+                let pos = match state.remote_positions.get(id).or(player.pos.as_ref()) {
+                    Some(pos) => pos.clone(),
+                    None => continue,
+                };
+                draw_player(
+                    trail.clone(),
+                    &theme,
+                    pos,
+                    '●',
+                    state.color_of(id, &theme),
+                    true,
+                    &scroll.clone(),
+                    &centering,
+                    &area,
+                    buf,
+                );
+            }
+            if let Some(pos) = player_pos {
                 draw_player(
-                    pos_history,
-                    player_pos,
+                    trail,
+                    &theme,
+                    pos,
                     '●',
-                    Color::Cyan,
+                    theme.player,
+                    false,
                     &scroll,
                     &centering,
                     &area,
                     buf,
                 );
             }
-            _ => {}
         }
     }
 }
 
+/// Legend of everyone in the game, in the color they are drawn in,
+/// with where they are, and our own player or the one we follow marked
+fn player_list(state: &GameStateSynced, theme: &Theme) -> List<'static> {
+    let mut players: Vec<_> = state.player_dict.values().collect();
+    players.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+    let items: Vec<ListItem> = players
+        .into_iter()
+        .map(|player| {
+            let is_me = player.id == state.player_id;
+            let followed = state.following.as_ref() == Some(&player.id);
+            // our own rtt is fresher locally than the one we reported
+            let rtt = match (is_me, &state.ping_error) {
+                (true, Some(error)) => format!("ping failed: {}", error),
+                (true, None) => format!("{}ms", state.rtt_ms),
+                (false, _) => format!("{}ms", player.rtt_ms),
+            };
+            let name = match (is_me, followed) {
+                (true, _) => format!("{} (you)", player.name),
+                (false, true) => format!("{} (following)", player.name),
+                (false, false) => player.name.clone(),
+            };
+            let pos = state
+                .remote_positions
+                .get(&player.id)
+                .or(player.pos.as_ref())
+                .map(|pos| format!(" {},{}", pos.x, pos.y))
+                .unwrap_or_default();
+            ListItem::new(Spans::from(vec![
+                Span::styled("● ", Style::default().fg(state.color_of(&player.id, theme))),
+                Span::raw(name),
+                Span::styled(
+                    format!("{} {}", pos, rtt),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                Span::styled(
+                    if player.reached_goal { " ⚑" } else { "" },
+                    Style::default().fg(theme.goal),
+                ),
+            ]))
+        })
+        .collect();
+    let title = match state.spectators {
+        0 => "Players".to_string(),
+        count => format!("Players, {} watching", count),
+    };
+    List::new(items).block(Block::default().title(title).borders(Borders::ALL))
+}
+
 pub fn ui<B: Backend>(
     state: Option<Rc<RefCell<GameStateSynced>>>,
-    pos_history: Rc<RefCell<HashSet<(u32, u32)>>>,
+    trail: Rc<RefCell<Trail>>,
+    config: &Config,
+    f: &mut Frame<B>,
+) {
+    let theme = config.theme;
+    let accent = Style::default()
+        .fg(theme.accent)
+        .add_modifier(Modifier::BOLD);
+    let idle_deadline = state
+        .as_ref()
+        .and_then(|state_ref| state_ref.borrow().idle_deadline);
+    let header = match idle_deadline {
+        Some(deadline) => {
+            let seconds_left = deadline.saturating_duration_since(Instant::now()).as_secs();
+            vec![Spans::from(vec![Span::styled(
+                format!(
+                    "You will be kicked for being idle in {}s, move to keep playing!",
+                    seconds_left
+                ),
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            )])]
+        }
+        None => vec![Spans::from(vec![
+            Span::raw("Welcome to "),
+            Span::styled("mazeio", accent),
+            Span::raw(". the online multiplayer maze game!"),
+        ])],
+    };
+
+    let keys = &config.keys;
+    let mut instructions = vec![
+        Span::raw("Move "),
+        Span::styled(keys.describe(Action::MoveUp), accent),
+        Span::raw(" up, "),
+        Span::styled(keys.describe(Action::MoveLeft), accent),
+        Span::raw(" left, "),
+        Span::styled(keys.describe(Action::MoveDown), accent),
+        Span::raw(" down, "),
+        Span::styled(keys.describe(Action::MoveRight), accent),
+        Span::raw(" right. "),
+    ];
+    let chat_enabled = state
+        .as_ref()
+        .is_some_and(|state_ref| state_ref.borrow().max_chat_len != 0);
+    let autopilot = state.as_ref().and_then(|state_ref| {
+        let assist = &state_ref.borrow().assist;
+        assist.available.then_some(assist.autopilot)
+    });
+    instructions.push(Span::raw("Press "));
+    instructions.push(Span::styled(keys.describe(Action::Minimap), accent));
+    instructions.push(Span::raw(" for the map, "));
+    instructions.push(Span::styled(keys.describe(Action::Trail), accent));
+    instructions.push(Span::raw(" for the trail, "));
+    instructions.push(Span::styled(keys.describe(Action::Marker), accent));
+    instructions.push(Span::raw(" to drop a marker, "));
+    if let Some(autopilot) = autopilot {
+        instructions.push(Span::styled(keys.describe(Action::Hint), accent));
+        instructions.push(Span::raw(" to show the way, "));
+        instructions.push(Span::styled(keys.describe(Action::Target), accent));
+        instructions.push(Span::raw(" to head for the next marker, "));
+        instructions.push(Span::styled(keys.describe(Action::Autopilot), accent));
+        instructions.push(Span::raw(match autopilot {
+            true => " to stop the autopilot, ",
+            false => " for the autopilot, ",
+        }));
+        instructions.push(Span::styled("click", accent));
+        instructions.push(Span::raw(" a cell to walk there, "));
+    }
+    if chat_enabled {
+        instructions.push(Span::styled(keys.describe(Action::Chat), accent));
+        instructions.push(Span::raw(" to chat, "));
+    }
+    instructions.push(Span::styled(keys.describe(Action::Quit), accent));
+    instructions.push(Span::raw(" to exit."));
+    let mut header = header;
+    header.push(Spans::from(instructions));
+
+    let chat = state
+        .as_ref()
+        .map(|state_ref| chat_panel(&state_ref.borrow(), &theme, CHAT_HEIGHT - 2))
+        .unwrap_or_else(|| Paragraph::new(Vec::new()));
+    draw_screen(f, &theme, header, state, trail, chat, CHAT_HEIGHT);
+}
+
+/// Everyone's stats, best first, two lines each
+fn scoreboard(state: &GameStateSynced, theme: &Theme) -> List<'static> {
+    let items: Vec<ListItem> = state
+        .scoreboard
+        .iter()
+        .enumerate()
+        .map(|(i, stats)| {
+            let color = state.color_of(&stats.player_id, theme);
+            let mut title = vec![
+                Span::raw(format!("{}. ", i + 1)),
+                Span::styled(stats.name.clone(), Style::default().fg(color)),
+            ];
+            if stats.wins != 0 {
+                title.push(Span::styled(
+                    format!(" ★{}", stats.wins),
+                    Style::default().fg(theme.goal),
+                ));
+            }
+            if stats.time_to_exit_ms != 0 {
+                title.push(Span::styled(
+                    format!(" ⚑ {:.1}s", stats.time_to_exit_ms as f64 / 1000.0),
+                    Style::default().fg(theme.goal),
+                ));
+            }
+            let details = Span::styled(
+                format!(
+                    "   {} moves {} cells {} tags",
+                    stats.moves, stats.cells_explored, stats.tags
+                ),
+                Style::default().fg(Color::DarkGray),
+            );
+            ListItem::new(vec![Spans::from(title), Spans::from(details)])
+        })
+        .collect();
+    List::new(items).block(Block::default().title("Scoreboard").borders(Borders::ALL))
+}
+
+/// The latest chat messages that fit in height lines,
+/// and the message being typed if we are in chat mode
+fn chat_panel(state: &GameStateSynced, theme: &Theme, height: u16) -> Paragraph<'static> {
+    let accent = Style::default()
+        .fg(theme.accent)
+        .add_modifier(Modifier::BOLD);
+    let room = height as usize - state.chat_input.is_some() as usize;
+    let skip = state.chat_log.len().saturating_sub(room);
+    let mut lines: Vec<Spans> = state
+        .chat_log
+        .iter()
+        .skip(skip)
+        .map(|message| {
+            let color = state.color_of(&message.player_id, theme);
+            Spans::from(vec![
+                Span::styled(message.name.clone(), Style::default().fg(color)),
+                Span::raw(": "),
+                Span::raw(message.text.clone()),
+            ])
+        })
+        .collect();
+    let title = match &state.chat_input {
+        Some(input) => {
+            lines.push(Spans::from(vec![
+                Span::styled("> ", accent),
+                Span::raw(input.clone()),
+                Span::styled("█", accent),
+            ]));
+            "Chat (Enter to send, Esc to cancel)"
+        }
+        None => "Chat",
+    };
+    Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL))
+}
+
+/// The instructions panel shown below the game
+fn instructions_panel(instructions: Vec<Spans>) -> Paragraph {
+    let block = Block::default().title("Instructions").borders(Borders::ALL);
+    Paragraph::new(instructions)
+        .block(block)
+        .wrap(Wrap { trim: true })
+}
+
+/// Draw the game for a spectator, following the player whose id is in the state
+pub fn spectate_ui<B: Backend>(
+    state: Rc<RefCell<GameStateSynced>>,
+    trail: Rc<RefCell<Trail>>,
+    config: &Config,
     f: &mut Frame<B>,
 ) {
+    let theme = config.theme;
+    let accent = Style::default()
+        .fg(theme.accent)
+        .add_modifier(Modifier::BOLD);
+    let following = {
+        let state = state.borrow();
+        state
+            .player_dict
+            .get(&state.player_id)
+            .map(|player| player.name.clone())
+    };
+    let header = vec![Spans::from(match following {
+        Some(name) => vec![
+            Span::raw("Spectating, following "),
+            Span::styled(name, accent),
+        ],
+        None => vec![Span::raw("Spectating, nobody can see you")],
+    })];
+    let keys = &config.keys;
+    let instructions = vec![Spans::from(vec![
+        Span::raw("Look around with "),
+        Span::styled(keys.describe(Action::MoveUp), accent),
+        Span::raw("/"),
+        Span::styled(keys.describe(Action::MoveLeft), accent),
+        Span::raw("/"),
+        Span::styled(keys.describe(Action::MoveDown), accent),
+        Span::raw("/"),
+        Span::styled(keys.describe(Action::MoveRight), accent),
+        Span::raw(", "),
+        Span::styled("Tab", accent),
+        Span::raw(" follow next player. Press "),
+        Span::styled(keys.describe(Action::Minimap), accent),
+        Span::raw(" for the map, "),
+        Span::styled(keys.describe(Action::Quit), accent),
+        Span::raw(" to exit."),
+    ])];
+    draw_screen(
+        f,
+        &theme,
+        header,
+        Some(state),
+        trail,
+        instructions_panel(instructions),
+        INSTRUCTIONS_HEIGHT,
+    );
+}
+
+/// Draw a replay, following the player whose id is in the state
+pub fn replay_ui<B: Backend>(
+    state: Rc<RefCell<GameStateSynced>>,
+    trail: Rc<RefCell<Trail>>,
+    status: String,
+    config: &Config,
+    f: &mut Frame<B>,
+) {
+    let theme = config.theme;
+    let accent = Style::default()
+        .fg(theme.accent)
+        .add_modifier(Modifier::BOLD);
+    let header = vec![Spans::from(vec![Span::raw(status)])];
+    let instructions = vec![Spans::from(vec![
+        Span::styled("Space", accent),
+        Span::raw(" pause, "),
+        Span::styled("←/→", accent),
+        Span::raw(" seek, "),
+        Span::styled("-/+", accent),
+        Span::raw(" speed, "),
+        Span::styled("Tab", accent),
+        Span::raw(" follow next player. Press "),
+        Span::styled(config.keys.describe(Action::Minimap), accent),
+        Span::raw(" for the map, "),
+        Span::styled(config.keys.describe(Action::Quit), accent),
+        Span::raw(" to exit."),
+    ])];
+    draw_screen(
+        f,
+        &theme,
+        header,
+        Some(state),
+        trail,
+        instructions_panel(instructions),
+        INSTRUCTIONS_HEIGHT,
+    );
+}
+
+/// Draw a page of the leaderboard, without any game
+pub fn leaderboard_ui<B: Backend>(view: &LeaderboardView, config: &Config, f: &mut Frame<B>) {
+    let theme = config.theme;
+    let accent = Style::default()
+        .fg(theme.accent)
+        .add_modifier(Modifier::BOLD);
     let chunks = Layout::default()
         .direction(tui::layout::Direction::Vertical)
         .constraints(
             [
                 Constraint::Length(3),
-                Constraint::Percentage(80),
-                Constraint::Percentage(10),
+                Constraint::Min(0),
+                Constraint::Length(INSTRUCTIONS_HEIGHT),
             ]
             .as_ref(),
         )
         .split(f.size());
 
-    // blocks
-    let block = Block::default().borders(Borders::ALL).title(Span::styled(
-        "Mazeio",
-        Style::default()
-            .fg(Color::Magenta)
-            .add_modifier(Modifier::BOLD),
-    ));
-    let text = vec![Spans::from(vec![
-        Span::raw("Welcome to "),
+    let title = match view.kind {
+        LeaderboardKind::BestTimes => {
+            let maze = view.page.as_ref().and_then(|page| page.maze.as_ref());
+            match maze {
+                Some(maze) => format!(
+                    "Best times on the {}x{} maze with seed {}",
+                    maze.width, maze.height, maze.seed
+                ),
+                None => "Best times".to_string(),
+            }
+        }
+        LeaderboardKind::Wins => "Most races won".to_string(),
+    };
+    let header = Paragraph::new(Spans::from(vec![
+        Span::raw(title),
         Span::styled(
-            "mazeio",
-            Style::default()
-                .fg(Color::Magenta)
-                .add_modifier(Modifier::BOLD),
+            format!("  {}", view.page_status()),
+            Style::default().fg(Color::DarkGray),
         ),
-        Span::raw(". the online multiplayer maze game!"),
+    ]))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(Span::styled("Mazeio Leaderboard", accent)),
+    );
+    f.render_widget(header, chunks[0]);
+
+    let items: Vec<ListItem> = match &view.page {
+        Some(page) if page.entries.is_empty() => {
+            vec![ListItem::new("Nobody made it onto the leaderboard yet")]
+        }
+        Some(page) => page
+            .entries
+            .iter()
+            .map(|entry| {
+                let score = match view.kind {
+                    LeaderboardKind::BestTimes => {
+                        format!(" ⚑ {:.1}s", entry.time_ms as f64 / 1000.0)
+                    }
+                    LeaderboardKind::Wins => format!(" ★{}", entry.wins),
+                };
+                let color = if entry.name == config.name {
+                    theme.player
+                } else {
+                    theme.opponent
+                };
+                ListItem::new(Spans::from(vec![
+                    Span::raw(format!("{:>4}. ", entry.rank)),
+                    Span::styled(entry.name.clone(), Style::default().fg(color)),
+                    Span::styled(score, Style::default().fg(theme.goal)),
+                ]))
+            })
+            .collect(),
+        None => vec![ListItem::new("Loading...")],
+    };
+    f.render_widget(
+        List::new(items).block(Block::default().borders(Borders::ALL)),
+        chunks[1],
+    );
+
+    let instructions = vec![Spans::from(vec![
+        Span::styled("Tab", accent),
+        Span::raw(" best times/wins, "),
+        Span::styled("←/→", accent),
+        Span::raw(" page. Press "),
+        Span::styled(config.keys.describe(Action::Quit), accent),
+        Span::raw(" to exit."),
     ])];
-    let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
+    f.render_widget(instructions_panel(instructions), chunks[2]);
+}
+
+/// Header, game view with the player list, and a footer panel
+#[allow(clippy::too_many_arguments)]
+fn draw_screen<B: Backend>(
+    f: &mut Frame<B>,
+    theme: &Theme,
+    header: Vec<Spans>,
+    state: Option<Rc<RefCell<GameStateSynced>>>,
+    trail: Rc<RefCell<Trail>>,
+    footer: Paragraph,
+    footer_height: u16,
+) {
+    let accent = Style::default()
+        .fg(theme.accent)
+        .add_modifier(Modifier::BOLD);
+    let chunks = Layout::default()
+        .direction(tui::layout::Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(header.len() as u16 + 2),
+                Constraint::Min(0),
+                Constraint::Length(footer_height),
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+
+    // blocks
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Span::styled("Mazeio", accent));
+    let paragraph = Paragraph::new(header)
+        .block(block)
+        .wrap(Wrap { trim: true });
     f.render_widget(paragraph, chunks[0]);
 
     // game section
+    let game_chunks = Layout::default()
+        .direction(tui::layout::Direction::Horizontal)
+        .constraints([Constraint::Min(0), Constraint::Length(SIDEBAR_WIDTH)].as_ref())
+        .split(chunks[1]);
+    let player_list = state
+        .as_ref()
+        .map(|state_ref| player_list(&state_ref.borrow(), theme));
+    let scoreboard = state
+        .as_ref()
+        .filter(|state_ref| !state_ref.borrow().scoreboard.is_empty())
+        .map(|state_ref| scoreboard(&state_ref.borrow(), theme));
+    let state_for_minimap = state.clone();
+    let trail_for_minimap = trail.clone();
     let block = Block::default().borders(Borders::LEFT | Borders::RIGHT);
+    if let Some(state_ref) = &state {
+        state_ref.borrow_mut().view_area = block.inner(game_chunks[0]);
+    }
     let game_view = GameView {
         state,
-        pos_history: pos_history.clone(),
+        trail,
+        theme: *theme,
     };
-    f.render_widget(game_view, block.inner(chunks[1]));
-    f.render_widget(block, chunks[1]);
+    f.render_widget(game_view, block.inner(game_chunks[0]));
+    f.render_widget(block, game_chunks[0]);
 
-    let block = Block::default().title("Instructions").borders(Borders::ALL);
-    let text = vec![Spans::from(vec![
-        Span::raw("Use "),
-        Span::styled(
-            "WASD",
-            Style::default()
-                .fg(Color::Magenta)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::raw(" to control your player. Press "),
-        Span::styled(
-            "Esc",
-            Style::default()
-                .fg(Color::Magenta)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::raw(" to exit."),
-    ])];
-    let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
-    f.render_widget(paragraph, chunks[2]);
+    // player list with pings, then the minimap and the scoreboard if we have them
+    let minimap_height = state_for_minimap
+        .as_ref()
+        .filter(|state_ref| state_ref.borrow().show_minimap)
+        .map(|state_ref| {
+            let maze = &state_ref.borrow().maze;
+            // the minimap has borders on every side
+            (minimap::height_for(maze.width, maze.height, SIDEBAR_WIDTH - 2) + 2)
+                .min(MINIMAP_MAX_HEIGHT)
+        });
+    let mut constraints = vec![Constraint::Min(0)];
+    if let Some(height) = minimap_height {
+        constraints.push(Constraint::Length(height));
+    }
+    if scoreboard.is_some() {
+        constraints.push(Constraint::Percentage(60));
+    }
+    let sidebar = Layout::default()
+        .direction(tui::layout::Direction::Vertical)
+        .constraints(constraints)
+        .split(game_chunks[1]);
+    if let Some(list) = player_list {
+        f.render_widget(list, sidebar[0]);
+    }
+    if let (Some(state_ref), Some(_)) = (&state_for_minimap, minimap_height) {
+        let block = Block::default().title("Map").borders(Borders::ALL);
+        let trail = trail_for_minimap.borrow();
+        let minimap = Minimap {
+            state: &state_ref.borrow(),
+            trail: &trail,
+            theme: *theme,
+        };
+        f.render_widget(minimap, block.inner(sidebar[1]));
+        f.render_widget(block, sidebar[1]);
+    }
+    if let Some(scoreboard) = scoreboard {
+        f.render_widget(scoreboard, sidebar[sidebar.len() - 1]);
+    }
+
+    f.render_widget(footer, chunks[2]);
 }
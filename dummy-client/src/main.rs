@@ -20,21 +20,54 @@ struct Args {
     delay_millis: u64,
 }
 
+/// Join the game, waiting in line if it is full and the server has a queue
+async fn join(
+    client: &mut GameClient<tonic::transport::Channel>,
+    name: String,
+    use_queue: bool,
+) -> Result<JoinGameResponse, tonic::Status> {
+    let request = JoinGameRequest {
+        name,
+        spectate: false,
+    };
+    match client.connect_player(Request::new(request.clone())).await {
+        Err(status) if use_queue && status.code() == tonic::Code::ResourceExhausted => {
+            let mut queue_stream = client.join_queue(Request::new(request)).await?.into_inner();
+            while let Some(queue_update) = queue_stream.message().await? {
+                if let Some(queue_update::Update::Joined(response)) = queue_update.update {
+                    return Ok(response);
+                }
+            }
+            Err(tonic::Status::unavailable("Server closed the join queue"))
+        }
+        res => res.map(|response| response.into_inner()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    let server_info = GameClient::connect("http://[::1]:50051")
+        .await?
+        .get_server_info(Request::new(ServerInfoRequest {}))
+        .await?
+        .into_inner();
+    if server_info.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Server speaks protocol version {} but dummy-client speaks {}",
+            server_info.protocol_version, PROTOCOL_VERSION
+        )
+        .into());
+    }
+    let use_queue = server_info.supports(features::JOIN_QUEUE);
     let mut handles = Vec::new();
     for i in 0..args.count {
         let handle = tokio::spawn(async move {
             let mut client = GameClient::connect("http://[::1]:50051").await.unwrap();
-            let name = format!("client-#{}", i+1);
+            let name = format!("client-#{}", i + 1);
+
+            let join_game_response = join(&mut client, name, use_queue).await.unwrap();
 
-            let join_game_response = client
-                .connect_player(Request::new(JoinGameRequest { name }))
-                .await
-                .unwrap()
-                .into_inner();
-            
             let maze = join_game_response.maze.unwrap();
             let players = join_game_response.players;
             let player_id = join_game_response.player_id;
@@ -50,21 +83,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             let mut timer =
                 tokio::time::interval(tokio::time::Duration::from_millis(args.delay_millis));
-            for _action in 0..args.actions {
-                let mut dir : Direction = rand::random();
+            for action in 0..args.actions {
+                let mut dir: Direction = rand::random();
                 while !my_player.move_if_valid(&maze, dir) {
                     dir = rand::random();
                 }
-                tx.send(InputDirection {
+                let input = InputDirection {
                     direction: dir.into(),
-                })
-                .await
-                .unwrap();
+                    seq: action as u32 + 1,
+                };
+                if tx.send(ClientMessage::input(input)).await.is_err() {
+                    println!("Server closed the game for client #{}", i + 1);
+                    return;
+                }
                 timer.tick().await;
             }
-            println!("Finished sending actions for client #{}", i+1);
+            println!("Finished sending actions for client #{}", i + 1);
         });
-        println!("Spawned client #{}", i+1);
+        println!("Spawned client #{}", i + 1);
         handles.push(handle);
     }
     join_all(handles).await;
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Decides what chat messages everyone else gets to see
+pub trait ChatFilter: fmt::Debug + Send + Sync {
+    /// The text to send, possibly changed, or None to drop the message
+    fn filter(&self, text: &str) -> Option<String>;
+}
+
+/// Masks blocked words with asterisks, ignoring case
+#[derive(Debug, Default)]
+pub struct WordFilter {
+    /// lowercase
+    words: HashSet<String>,
+}
+
+impl WordFilter {
+    pub fn new<I: IntoIterator<Item = String>>(words: I) -> Self {
+        Self {
+            words: words.into_iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+
+    /// Read a blocklist with one word per line, skipping blank lines and # comments
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(Self::new(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        ))
+    }
+}
+
+impl ChatFilter for WordFilter {
+    fn filter(&self, text: &str) -> Option<String> {
+        let mut filtered = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, filtered: &mut String| {
+            if self.words.contains(&word.to_lowercase()) {
+                filtered.extend(word.chars().map(|_| '*'));
+            } else {
+                filtered.push_str(word);
+            }
+            word.clear();
+        };
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut filtered);
+                filtered.push(c);
+            }
+        }
+        flush(&mut word, &mut filtered);
+        Some(filtered)
+    }
+}
+
+/// Remove anything that could mess with other players' terminals
+pub fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_blocked_words_only() {
+        let filter = WordFilter::new(["darn".to_string()]);
+        assert_eq!(
+            filter.filter("Darn, darnit! DARN.").as_deref(),
+            Some("****, darnit! ****.")
+        );
+        assert_eq!(sanitize(" hi\x1b[2J\n "), "hi[2J");
+    }
+}
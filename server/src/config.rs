@@ -0,0 +1,442 @@
+use clap::Parser;
+use mazeio_shared::{GameMode, MazeAlgorithm};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Config file used when none is given explicitly, if it exists
+const DEFAULT_CONFIG_PATH: &str = "mazeio.toml";
+
+/// The mazeio game server.
+///
+/// Settings are taken from command line flags first, then environment
+/// variables (also loaded from .env), then the config file, then defaults.
+#[derive(Parser, Debug, Default)]
+#[clap(about, version, author)]
+pub struct Args {
+    /// Path of a TOML config file [default: mazeio.toml, if present]
+    #[clap(short, long, env = "MAZEIO_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on [default: [::1]:50051]
+    #[clap(short, long, env = "MAZEIO_BIND")]
+    pub bind: Option<SocketAddr>,
+    /// Maze width, in open cells [default: 16]
+    #[clap(long, env = "MAZEIO_MAZE_WIDTH")]
+    pub maze_width: Option<usize>,
+    /// Maze height, in open cells [default: 32]
+    #[clap(long, env = "MAZEIO_MAZE_HEIGHT")]
+    pub maze_height: Option<usize>,
+    /// Maze generation algorithm: aldous-broder or recursive-backtracker [default: aldous-broder]
+    #[clap(long, env = "MAZEIO_MAZE_ALGORITHM")]
+    pub maze_algorithm: Option<String>,
+    /// Seed for maze generation [default: random]
+    #[clap(long, env = "MAZEIO_SEED")]
+    pub seed: Option<u64>,
+    /// Game mode: explore or race [default: explore]
+    #[clap(short, long, env = "MAZEIO_MODE")]
+    pub mode: Option<String>,
+    /// Server ticks per second [default: 20]
+    #[clap(long, env = "MAZEIO_TICK_RATE")]
+    pub tick_rate: Option<u32>,
+    /// Maximum number of players, 0 for unlimited [default: 0]
+    #[clap(long, env = "MAZEIO_MAX_PLAYERS")]
+    pub max_players: Option<u32>,
+    /// Clients allowed to wait in line for a full game, 0 to turn the queue off [default: 0]
+    #[clap(long, env = "MAZEIO_MAX_QUEUED")]
+    pub max_queued: Option<u32>,
+    /// Log output format: pretty, compact or json [default: pretty]
+    #[clap(long, env = "MAZEIO_LOG_FORMAT")]
+    pub log_format: Option<String>,
+    /// Updates buffered per client before it is resynced [default: 50]
+    #[clap(long, env = "MAZEIO_BROADCAST_CAPACITY")]
+    pub broadcast_capacity: Option<usize>,
+    /// Seconds without input before a player is kicked, 0 to never kick [default: 300]
+    #[clap(long, env = "MAZEIO_IDLE_TIMEOUT_SECS")]
+    pub idle_timeout_secs: Option<u64>,
+    /// Seconds before being kicked that idle players are warned [default: 30]
+    #[clap(long, env = "MAZEIO_IDLE_WARNING_SECS")]
+    pub idle_warning_secs: Option<u64>,
+    /// Moves per second a player can sustain, 0 for unlimited [default: 20]
+    #[clap(long, env = "MAZEIO_MAX_MOVES_PER_SEC")]
+    pub max_moves_per_sec: Option<u32>,
+    /// Moves a player can make at once after standing still [default: 5]
+    #[clap(long, env = "MAZEIO_MOVE_BURST")]
+    pub move_burst: Option<u32>,
+    /// Suspicious inputs before a player is kicked, 0 to never kick [default: 0]
+    #[clap(long, env = "MAZEIO_MAX_STRIKES")]
+    pub max_strikes: Option<u32>,
+    /// Seconds to wait for clients to leave when shutting down [default: 5]
+    #[clap(long, env = "MAZEIO_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// File the game state is saved to, and restored from at startup [default: none]
+    #[clap(long, env = "MAZEIO_STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+    /// Seconds between saves of the game state, 0 to only save on shutdown [default: 60]
+    #[clap(long, env = "MAZEIO_SAVE_INTERVAL_SECS")]
+    pub save_interval_secs: Option<u64>,
+    /// Seconds a player who left can rejoin under the same name and
+    /// pick up where they left off, 0 to always start over [default: 60]
+    #[clap(long, env = "MAZEIO_RECONNECT_GRACE_SECS")]
+    pub reconnect_grace_secs: Option<u64>,
+    /// File to record a replay of the game to [default: none]
+    #[clap(long, env = "MAZEIO_REPLAY_FILE")]
+    pub replay_file: Option<PathBuf>,
+    /// Longest chat message in characters, 0 to turn chat off [default: 200]
+    #[clap(long, env = "MAZEIO_MAX_CHAT_LEN")]
+    pub max_chat_len: Option<u32>,
+    /// Chat messages per second a player can sustain, 0 for unlimited [default: 1]
+    #[clap(long, env = "MAZEIO_MAX_CHATS_PER_SEC")]
+    pub max_chats_per_sec: Option<u32>,
+    /// Chat messages a player can send at once after being quiet [default: 5]
+    #[clap(long, env = "MAZEIO_CHAT_BURST")]
+    pub chat_burst: Option<u32>,
+    /// File of words to mask in chat, one per line [default: none]
+    #[clap(long, env = "MAZEIO_CHAT_BLOCKLIST")]
+    pub chat_blocklist: Option<PathBuf>,
+    /// SQLite database to keep best times and wins in across games [default: none]
+    #[clap(long, env = "MAZEIO_LEADERBOARD_DB")]
+    pub leaderboard_db: Option<PathBuf>,
+    /// Steps through open cells players can see, 0 to show everyone everything [default: 0]
+    #[clap(long, env = "MAZEIO_SIGHT_RADIUS")]
+    pub sight_radius: Option<u32>,
+    /// Game modes where clients may show the way to the goal and move
+    /// players along it, comma separated, or none [default: explore]
+    #[clap(long, env = "MAZEIO_ASSIST_MODES")]
+    pub assist_modes: Option<String>,
+}
+
+/// Contents of the config file, every key is optional
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub bind: Option<SocketAddr>,
+    pub maze_width: Option<usize>,
+    pub maze_height: Option<usize>,
+    pub maze_algorithm: Option<String>,
+    pub seed: Option<u64>,
+    pub mode: Option<String>,
+    pub tick_rate: Option<u32>,
+    pub max_players: Option<u32>,
+    pub max_queued: Option<u32>,
+    pub log_format: Option<String>,
+    pub broadcast_capacity: Option<usize>,
+    pub idle_timeout_secs: Option<u64>,
+    pub idle_warning_secs: Option<u64>,
+    pub max_moves_per_sec: Option<u32>,
+    pub move_burst: Option<u32>,
+    pub max_strikes: Option<u32>,
+    pub shutdown_timeout_secs: Option<u64>,
+    pub state_file: Option<PathBuf>,
+    pub save_interval_secs: Option<u64>,
+    pub reconnect_grace_secs: Option<u64>,
+    pub replay_file: Option<PathBuf>,
+    pub max_chat_len: Option<u32>,
+    pub max_chats_per_sec: Option<u32>,
+    pub chat_burst: Option<u32>,
+    pub chat_blocklist: Option<PathBuf>,
+    pub leaderboard_db: Option<PathBuf>,
+    pub sight_radius: Option<u32>,
+    pub assist_modes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Pretty,
+    Compact,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Unknown log format '{}', expected pretty, compact or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Fully resolved server settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub maze_width: usize,
+    pub maze_height: usize,
+    pub maze_algorithm: MazeAlgorithm,
+    /// None picks a random seed at startup
+    pub seed: Option<u64>,
+    pub mode: GameMode,
+    pub tick_rate: u32,
+    /// 0 means unlimited
+    pub max_players: u32,
+    /// 0 means there is no join queue
+    pub max_queued: u32,
+    pub log_format: LogFormat,
+    pub broadcast_capacity: usize,
+    /// 0 means players are never kicked
+    pub idle_timeout_secs: u64,
+    pub idle_warning_secs: u64,
+    /// 0 means unlimited
+    pub max_moves_per_sec: u32,
+    pub move_burst: u32,
+    /// 0 means players are never kicked
+    pub max_strikes: u32,
+    pub shutdown_timeout_secs: u64,
+    /// None means the state is not saved
+    pub state_file: Option<PathBuf>,
+    /// 0 means only saving on shutdown
+    pub save_interval_secs: u64,
+    /// 0 means players who leave always start over
+    pub reconnect_grace_secs: u64,
+    /// None means no replay is recorded
+    pub replay_file: Option<PathBuf>,
+    /// 0 means chat is off
+    pub max_chat_len: u32,
+    /// 0 means unlimited
+    pub max_chats_per_sec: u32,
+    pub chat_burst: u32,
+    /// None means chat isn't filtered
+    pub chat_blocklist: Option<PathBuf>,
+    /// None means there is no leaderboard
+    pub leaderboard_db: Option<PathBuf>,
+    /// 0 means no fog of war, and spectators are allowed
+    pub sight_radius: u32,
+    /// empty means hints and autopilots are never allowed
+    pub assist_modes: Vec<GameMode>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "[::1]:50051".parse().unwrap(),
+            maze_width: 16,
+            maze_height: 32,
+            maze_algorithm: MazeAlgorithm::AldousBroder,
+            seed: None,
+            mode: GameMode::Explore,
+            tick_rate: 20,
+            max_players: 0,
+            max_queued: 0,
+            log_format: LogFormat::Pretty,
+            broadcast_capacity: 50,
+            idle_timeout_secs: 300,
+            idle_warning_secs: 30,
+            max_moves_per_sec: 20,
+            move_burst: 5,
+            max_strikes: 0,
+            shutdown_timeout_secs: 5,
+            state_file: None,
+            save_interval_secs: 60,
+            reconnect_grace_secs: 60,
+            replay_file: None,
+            max_chat_len: 200,
+            max_chats_per_sec: 1,
+            chat_burst: 5,
+            chat_blocklist: None,
+            leaderboard_db: None,
+            sight_radius: 0,
+            assist_modes: vec![GameMode::Explore],
+        }
+    }
+}
+
+impl Config {
+    /// Read the command line, environment and config file
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => FileConfig::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                FileConfig::read(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => FileConfig::default(),
+        };
+        Self::merge(args, file)
+    }
+
+    /// Combine settings, preferring args (which already include env vars) over the file
+    pub fn merge(args: Args, file: FileConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let default = Config::default();
+        let config = Config {
+            bind: args.bind.or(file.bind).unwrap_or(default.bind),
+            maze_width: args
+                .maze_width
+                .or(file.maze_width)
+                .unwrap_or(default.maze_width),
+            maze_height: args
+                .maze_height
+                .or(file.maze_height)
+                .unwrap_or(default.maze_height),
+            maze_algorithm: match args.maze_algorithm.or(file.maze_algorithm) {
+                Some(s) => s.parse()?,
+                None => default.maze_algorithm,
+            },
+            seed: args.seed.or(file.seed),
+            mode: match args.mode.or(file.mode) {
+                Some(s) => s.parse()?,
+                None => default.mode,
+            },
+            tick_rate: args
+                .tick_rate
+                .or(file.tick_rate)
+                .unwrap_or(default.tick_rate),
+            max_players: args
+                .max_players
+                .or(file.max_players)
+                .unwrap_or(default.max_players),
+            max_queued: args
+                .max_queued
+                .or(file.max_queued)
+                .unwrap_or(default.max_queued),
+            log_format: match args.log_format.or(file.log_format) {
+                Some(s) => s.parse()?,
+                None => default.log_format,
+            },
+            broadcast_capacity: args
+                .broadcast_capacity
+                .or(file.broadcast_capacity)
+                .unwrap_or(default.broadcast_capacity),
+            idle_timeout_secs: args
+                .idle_timeout_secs
+                .or(file.idle_timeout_secs)
+                .unwrap_or(default.idle_timeout_secs),
+            idle_warning_secs: args
+                .idle_warning_secs
+                .or(file.idle_warning_secs)
+                .unwrap_or(default.idle_warning_secs),
+            max_moves_per_sec: args
+                .max_moves_per_sec
+                .or(file.max_moves_per_sec)
+                .unwrap_or(default.max_moves_per_sec),
+            move_burst: args
+                .move_burst
+                .or(file.move_burst)
+                .unwrap_or(default.move_burst),
+            max_strikes: args
+                .max_strikes
+                .or(file.max_strikes)
+                .unwrap_or(default.max_strikes),
+            shutdown_timeout_secs: args
+                .shutdown_timeout_secs
+                .or(file.shutdown_timeout_secs)
+                .unwrap_or(default.shutdown_timeout_secs),
+            state_file: args.state_file.or(file.state_file),
+            save_interval_secs: args
+                .save_interval_secs
+                .or(file.save_interval_secs)
+                .unwrap_or(default.save_interval_secs),
+            reconnect_grace_secs: args
+                .reconnect_grace_secs
+                .or(file.reconnect_grace_secs)
+                .unwrap_or(default.reconnect_grace_secs),
+            replay_file: args.replay_file.or(file.replay_file),
+            max_chat_len: args
+                .max_chat_len
+                .or(file.max_chat_len)
+                .unwrap_or(default.max_chat_len),
+            max_chats_per_sec: args
+                .max_chats_per_sec
+                .or(file.max_chats_per_sec)
+                .unwrap_or(default.max_chats_per_sec),
+            chat_burst: args
+                .chat_burst
+                .or(file.chat_burst)
+                .unwrap_or(default.chat_burst),
+            chat_blocklist: args.chat_blocklist.or(file.chat_blocklist),
+            leaderboard_db: args.leaderboard_db.or(file.leaderboard_db),
+            sight_radius: args
+                .sight_radius
+                .or(file.sight_radius)
+                .unwrap_or(default.sight_radius),
+            assist_modes: match args.assist_modes.map(split_modes).or(file.assist_modes) {
+                Some(names) => names
+                    .iter()
+                    .map(|name| name.parse())
+                    .collect::<Result<_, _>>()?,
+                None => default.assist_modes,
+            },
+        };
+        if config.maze_width == 0 || config.maze_height == 0 {
+            return Err("Maze width and height must be at least 1".into());
+        }
+        if config.tick_rate == 0 {
+            return Err("Tick rate must be at least 1".into());
+        }
+        if config.broadcast_capacity == 0 {
+            return Err("Broadcast capacity must be at least 1".into());
+        }
+        if config.idle_timeout_secs != 0 && config.idle_warning_secs >= config.idle_timeout_secs {
+            return Err("Idle warning must come before the idle timeout".into());
+        }
+        if config.move_burst == 0 {
+            return Err("Move burst must be at least 1".into());
+        }
+        if config.chat_burst == 0 {
+            return Err("Chat burst must be at least 1".into());
+        }
+        Ok(config)
+    }
+}
+
+/// "explore,race" as a list of mode names, with "none" being an empty one
+fn split_modes(names: String) -> Vec<String> {
+    names
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && name != "none")
+        .collect()
+}
+
+impl FileConfig {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_override_file_override_defaults() {
+        let file: FileConfig = toml::from_str(
+            r#"
+            maze-width = 8
+            maze-height = 9
+            mode = "race"
+            assist-modes = ["race"]
+            "#,
+        )
+        .unwrap();
+        let args = Args {
+            maze_width: Some(4),
+            assist_modes: Some("none".to_string()),
+            ..Args::default()
+        };
+        let config = Config::merge(args, file).unwrap();
+        assert_eq!(config.maze_width, 4);
+        assert_eq!(config.maze_height, 9);
+        assert_eq!(config.mode, GameMode::Race);
+        assert_eq!(config.tick_rate, Config::default().tick_rate);
+        assert!(config.assist_modes.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let args = Args {
+            mode: Some("deathmatch".to_string()),
+            ..Args::default()
+        };
+        assert!(Config::merge(args, FileConfig::default()).is_err());
+        assert!(toml::from_str::<FileConfig>("maze-colour = 3").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use mazeio_shared::tonic::Status;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Everything that can go wrong while handling a client request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerError {
+    /// the transport didn't tell us who the client is, and the
+    /// remote address is how we identify clients
+    MissingRemoteAddr,
+    /// the client tried to join again on the same connection
+    AlreadyJoined(SocketAddr),
+    /// the client sent game input without joining first
+    NotJoined(SocketAddr),
+    /// the client sent a direction that doesn't exist
+    InvalidDirection(i32),
+    /// the game already has the configured maximum number of players
+    GameFull(u32),
+    /// the client is already waiting in the join queue
+    AlreadyQueued(SocketAddr),
+    /// the join queue already has the configured maximum number of clients
+    QueueFull(u32),
+    /// the server was configured without a join queue
+    QueueDisabled,
+    /// the client sent moves faster than the configured rate
+    RateLimited(SocketAddr),
+    /// the client sent an input with a seq it already used
+    StaleInput { seq: u32, last_seq: u32 },
+    /// the client sent too many suspicious inputs and was kicked
+    TooManyStrikes(u32),
+    /// the server is shutting down and takes no new players
+    ShuttingDown,
+    /// the client sent game input while spectating
+    Spectating(SocketAddr),
+    /// the client tried to spectate a game with fog of war
+    SpectatingDisabled,
+    /// the server was configured without chat
+    ChatDisabled,
+    /// the client sent a chat message over the configured length
+    ChatTooLong(u32),
+    /// the client sent chat messages faster than the configured rate
+    ChatRateLimited(SocketAddr),
+    /// the client asked for a name it can't have, with why
+    InvalidName(String),
+    /// the server was configured without a leaderboard
+    LeaderboardDisabled,
+    /// the leaderboard database failed, with what it said
+    Leaderboard(String),
+}
+
+impl ServerError {
+    /// Whether the offending input can just be dropped, instead
+    /// of ending the client's stream
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            ServerError::RateLimited(_)
+                | ServerError::StaleInput { .. }
+                | ServerError::ChatDisabled
+                | ServerError::ChatTooLong(_)
+                | ServerError::ChatRateLimited(_)
+        )
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::MissingRemoteAddr => write!(f, "Could not determine client address"),
+            ServerError::AlreadyJoined(addr) => {
+                write!(f, "Client at {} has already joined the game", addr)
+            }
+            ServerError::NotJoined(addr) => {
+                write!(f, "Client at {} has not joined the game", addr)
+            }
+            ServerError::InvalidDirection(dir) => write!(f, "Invalid direction: {}", dir),
+            ServerError::GameFull(max) => write!(f, "Game is full ({} players)", max),
+            ServerError::AlreadyQueued(addr) => {
+                write!(f, "Client at {} is already waiting to join", addr)
+            }
+            ServerError::QueueFull(max) => write!(f, "Join queue is full ({} waiting)", max),
+            ServerError::QueueDisabled => write!(f, "Server has no join queue"),
+            ServerError::RateLimited(addr) => write!(f, "Client at {} is moving too fast", addr),
+            ServerError::StaleInput { seq, last_seq } => write!(
+                f,
+                "Input seq {} is not after the last applied seq {}",
+                seq, last_seq
+            ),
+            ServerError::TooManyStrikes(strikes) => {
+                write!(f, "Kicked after {} suspicious inputs", strikes)
+            }
+            ServerError::ShuttingDown => write!(f, "Server is shutting down"),
+            ServerError::Spectating(addr) => {
+                write!(f, "Client at {} is spectating and can't move", addr)
+            }
+            ServerError::SpectatingDisabled => {
+                write!(f, "Server has fog of war, so nobody can spectate")
+            }
+            ServerError::ChatDisabled => write!(f, "Server has chat turned off"),
+            ServerError::ChatTooLong(max) => {
+                write!(f, "Chat message is longer than {} characters", max)
+            }
+            ServerError::ChatRateLimited(addr) => {
+                write!(f, "Client at {} is chatting too fast", addr)
+            }
+            ServerError::InvalidName(reason) => write!(f, "Invalid name: {}", reason),
+            ServerError::LeaderboardDisabled => write!(f, "Server has no leaderboard"),
+            ServerError::Leaderboard(e) => write!(f, "Could not read the leaderboard: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl From<ServerError> for Status {
+    fn from(err: ServerError) -> Self {
+        let message = err.to_string();
+        match err {
+            ServerError::MissingRemoteAddr => Status::internal(message),
+            ServerError::AlreadyJoined(_) => Status::already_exists(message),
+            ServerError::NotJoined(_) => Status::failed_precondition(message),
+            ServerError::InvalidDirection(_) => Status::invalid_argument(message),
+            ServerError::GameFull(_) => Status::resource_exhausted(message),
+            ServerError::AlreadyQueued(_) => Status::already_exists(message),
+            ServerError::QueueFull(_) => Status::resource_exhausted(message),
+            ServerError::QueueDisabled => Status::failed_precondition(message),
+            ServerError::RateLimited(_) => Status::resource_exhausted(message),
+            ServerError::StaleInput { .. } => Status::invalid_argument(message),
+            ServerError::TooManyStrikes(_) => Status::permission_denied(message),
+            ServerError::ShuttingDown => Status::unavailable(message),
+            ServerError::Spectating(_) => Status::failed_precondition(message),
+            ServerError::SpectatingDisabled => Status::failed_precondition(message),
+            ServerError::ChatDisabled => Status::failed_precondition(message),
+            ServerError::ChatTooLong(_) => Status::invalid_argument(message),
+            ServerError::ChatRateLimited(_) => Status::resource_exhausted(message),
+            ServerError::InvalidName(_) => Status::invalid_argument(message),
+            ServerError::LeaderboardDisabled => Status::failed_precondition(message),
+            ServerError::Leaderboard(_) => Status::internal(message),
+        }
+    }
+}
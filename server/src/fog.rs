@@ -0,0 +1,314 @@
+use mazeio_shared::{
+    game_update, Cell, CellType, GameUpdate, Player, Position, ProtoMaze, RevealedCells, Snapshot,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Cells seen from pos, going at most radius steps through open cells.
+/// The walls around the open cells reached are seen too, corners included.
+pub fn visible_from(maze: &ProtoMaze, pos: &Position, radius: u32) -> HashSet<(u32, u32)> {
+    let mut visible = HashSet::from([(pos.x, pos.y)]);
+    let mut queue = VecDeque::from([(pos.x, pos.y, 0)]);
+    while let Some((x, y, steps)) = queue.pop_front() {
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ] {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            if nx < 0 || ny < 0 || nx >= maze.width as i64 || ny >= maze.height as i64 {
+                continue;
+            }
+            let (nx, ny) = (nx as u32, ny as u32);
+            if maze.get(nx as usize, ny as usize) != CellType::Open {
+                visible.insert((nx, ny));
+            } else if dx * dy == 0 && steps < radius && visible.insert((nx, ny)) {
+                queue.push_back((nx, ny, steps + 1));
+            }
+        }
+    }
+    visible
+}
+
+/// update with every position left out, for clients whose sight we don't
+/// know, such as one whose player has already left the game
+pub fn blind(update: GameUpdate) -> GameUpdate {
+    let hidden = |mut player: Player| {
+        player.pos = None;
+        player
+    };
+    let server_time_ms = update.server_time_ms;
+    let update = match update.update {
+        Some(game_update::Update::Player(player)) => {
+            Some(game_update::Update::Player(hidden(player)))
+        }
+        Some(game_update::Update::Snapshot(Snapshot { players })) => {
+            Some(game_update::Update::Snapshot(Snapshot {
+                players: players.into_iter().map(hidden).collect(),
+            }))
+        }
+        other => other,
+    };
+    GameUpdate {
+        server_time_ms,
+        update,
+    }
+}
+
+/// What one player has seen of the maze, and which opponents they can see
+#[derive(Debug)]
+pub struct Sight {
+    player_id: String,
+    radius: u32,
+    /// cells in sight right now
+    visible: HashSet<(u32, u32)>,
+    /// cells in sight now or before
+    seen: HashSet<(u32, u32)>,
+    /// latest state of every opponent, seen or not, by id
+    opponents: HashMap<String, Player>,
+}
+
+impl Sight {
+    /// What player sees when joining, with opponents being everyone else
+    /// in players, and seen what they saw before if they are coming back
+    pub fn new(
+        player: &Player,
+        maze: &ProtoMaze,
+        radius: u32,
+        players: &[Player],
+        seen: HashSet<(u32, u32)>,
+    ) -> Self {
+        let mut sight = Self {
+            player_id: player.id.clone(),
+            radius,
+            visible: HashSet::new(),
+            seen,
+            opponents: HashMap::new(),
+        };
+        for other in players {
+            sight.remember(other);
+        }
+        if let Some(pos) = &player.pos {
+            sight.look(maze, pos);
+        }
+        sight
+    }
+
+    /// Every cell seen so far, to remember for when the player comes back
+    pub fn into_seen(self) -> HashSet<(u32, u32)> {
+        self.seen
+    }
+
+    fn remember(&mut self, player: &Player) {
+        if player.id == self.player_id {
+            return;
+        }
+        if player.alive {
+            self.opponents.insert(player.id.clone(), player.clone());
+        } else {
+            self.opponents.remove(&player.id);
+        }
+    }
+
+    /// Look around from pos, returning the cells seen for the first time
+    fn look(&mut self, maze: &ProtoMaze, pos: &Position) -> Vec<(u32, u32)> {
+        self.visible = visible_from(maze, pos, self.radius);
+        let mut new: Vec<(u32, u32)> = self
+            .visible
+            .iter()
+            .filter(|cell| self.seen.insert(**cell))
+            .copied()
+            .collect();
+        new.sort_unstable();
+        new
+    }
+
+    /// The maze as far as the player has seen it. The seed is left out,
+    /// since the maze could be generated again from it. So is the goal until
+    /// seen, though that only keeps it off the screen, as generated mazes
+    /// always have it in the corner across from the start.
+    pub fn masked_maze(&self, maze: &ProtoMaze) -> ProtoMaze {
+        let mut masked = maze.clone();
+        for y in 0..maze.height {
+            for x in 0..maze.width {
+                if !self.seen.contains(&(x, y)) {
+                    masked.set(x as usize, y as usize, CellType::Unknown);
+                }
+            }
+        }
+        masked.goal = maze
+            .goal
+            .clone()
+            .filter(|goal| self.seen.contains(&(goal.x, goal.y)));
+        masked.seed = 0;
+        masked
+    }
+
+    /// player as the player gets to see them, without a position when out of sight
+    pub fn hide(&self, player: &Player) -> Player {
+        let mut player = player.clone();
+        let in_sight = player
+            .pos
+            .as_ref()
+            .is_some_and(|pos| self.visible.contains(&(pos.x, pos.y)));
+        if player.id != self.player_id && !in_sight {
+            player.pos = None;
+        }
+        player
+    }
+
+    /// The updates the player gets instead of update
+    pub fn filter(&mut self, maze: &ProtoMaze, update: GameUpdate) -> Vec<GameUpdate> {
+        let server_time_ms = update.server_time_ms;
+        let stamped = |update| GameUpdate {
+            server_time_ms,
+            update: Some(update),
+        };
+        match update.update {
+            Some(game_update::Update::Player(player)) if player.id == self.player_id => {
+                let mut updates = self.moved(maze, &player);
+                updates.insert(0, stamped(game_update::Update::Player(player)));
+                updates
+            }
+            Some(game_update::Update::Player(player)) => {
+                self.remember(&player);
+                vec![stamped(game_update::Update::Player(self.hide(&player)))]
+            }
+            Some(game_update::Update::Snapshot(Snapshot { players })) => {
+                self.opponents.clear();
+                for player in &players {
+                    self.remember(player);
+                }
+                let me = players.iter().find(|player| player.id == self.player_id);
+                let revealed = me.map(|me| self.moved(maze, me)).unwrap_or_default();
+                let players = players.iter().map(|player| self.hide(player)).collect();
+                let mut updates =
+                    vec![stamped(game_update::Update::Snapshot(Snapshot { players }))];
+                // the snapshot already has every opponent in it
+                updates.extend(revealed.into_iter().filter(|update| {
+                    matches!(update.update, Some(game_update::Update::Revealed(_)))
+                }));
+                updates
+            }
+            other => vec![GameUpdate {
+                server_time_ms,
+                update: other,
+            }],
+        }
+    }
+
+    /// Look around from where the player moved to, returning the cells they
+    /// saw for the first time and the opponents who came into or out of sight
+    fn moved(&mut self, maze: &ProtoMaze, player: &Player) -> Vec<GameUpdate> {
+        let pos = match (&player.pos, player.alive) {
+            (Some(pos), true) => pos,
+            _ => return Vec::new(),
+        };
+        let was_visible = std::mem::take(&mut self.visible);
+        let new = self.look(maze, pos);
+        let mut updates = Vec::new();
+        if !new.is_empty() {
+            let goal = maze
+                .goal
+                .clone()
+                .filter(|goal| new.contains(&(goal.x, goal.y)));
+            let cells = new
+                .into_iter()
+                .map(|(x, y)| Cell {
+                    x,
+                    y,
+                    r#type: maze.get(x as usize, y as usize).into(),
+                })
+                .collect();
+            updates.push(GameUpdate::revealed(RevealedCells { cells, goal }));
+        }
+        let mut changed: Vec<&Player> = self
+            .opponents
+            .values()
+            .filter(|opponent| {
+                opponent.pos.as_ref().is_some_and(|pos| {
+                    was_visible.contains(&(pos.x, pos.y)) != self.visible.contains(&(pos.x, pos.y))
+                })
+            })
+            .collect();
+        changed.sort_by(|a, b| a.id.cmp(&b.id));
+        updates.extend(
+            changed
+                .into_iter()
+                .map(|opponent| GameUpdate::player(self.hide(opponent))),
+        );
+        updates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a single corridor along the middle row, walled in
+    fn corridor_maze() -> ProtoMaze {
+        let mut maze = ProtoMaze {
+            width: 9,
+            height: 3,
+            cells: vec![CellType::Wall as i32; 27],
+            goal: Some(Position::new(7, 1)),
+            seed: 42,
+        };
+        for x in 1..8 {
+            maze.set(x, 1, CellType::Open);
+        }
+        maze
+    }
+
+    fn player(id: &str, x: u32) -> Player {
+        let mut player = Player::new(id.to_string());
+        player.id = id.to_string();
+        player.pos = Some(Position::new(x, 1));
+        player
+    }
+
+    #[test]
+    fn only_nearby_cells_and_opponents_are_seen() {
+        let maze = corridor_maze();
+        let me = player("me", 1);
+        let other = player("other", 5);
+        let mut sight = Sight::new(&me, &maze, 2, &[me.clone(), other.clone()], HashSet::new());
+        let masked = sight.masked_maze(&maze);
+        assert_eq!(masked.seed, 0);
+        assert_eq!(masked.goal, None);
+        assert_eq!(masked.get(3, 1), CellType::Open);
+        assert_eq!(masked.get(4, 0), CellType::Wall);
+        assert_eq!(masked.get(4, 1), CellType::Unknown);
+        assert_eq!(sight.hide(&other).pos, None);
+
+        // stepping closer brings the other player into sight
+        let updates = sight.filter(&maze, GameUpdate::player(player("me", 3)));
+        assert_eq!(updates.len(), 3);
+        match &updates[1].update {
+            Some(game_update::Update::Revealed(revealed)) => {
+                let xs: Vec<u32> = revealed.cells.iter().map(|cell| cell.x).collect();
+                assert_eq!(xs, vec![4, 5, 5, 5, 6, 6]);
+                assert_eq!(revealed.goal, None);
+            }
+            other => panic!("expected revealed cells, got {:?}", other),
+        }
+        match &updates[2].update {
+            Some(game_update::Update::Player(player)) => {
+                assert_eq!(player.id, "other");
+                assert_eq!(player.pos, Some(Position::new(5, 1)));
+            }
+            other => panic!("expected the other player, got {:?}", other),
+        }
+
+        // and moving away from us takes them out of it again
+        let updates = sight.filter(&maze, GameUpdate::player(player("other", 7)));
+        match &updates[0].update {
+            Some(game_update::Update::Player(player)) => assert_eq!(player.pos, None),
+            other => panic!("expected the other player, got {:?}", other),
+        }
+    }
+}
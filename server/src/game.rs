@@ -0,0 +1,2042 @@
+// shared libs
+use mazeio_shared::*;
+
+// autogenerated from proto file
+use mazeio_proto::game_server::Game;
+
+// async
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, oneshot, Mutex, RwLock, RwLockWriteGuard};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Duration, Instant};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, ReceiverStream};
+use tonic::{Request, Response, Status, Streaming};
+
+// logging
+use tracing::{debug, error, info, instrument, trace, warn};
+
+use crate::chat::{self, ChatFilter, WordFilter};
+use crate::config::Config;
+use crate::error::ServerError;
+use crate::fog::{self, Sight};
+use crate::leaderboard::{self, LeaderboardDb};
+use crate::names;
+use crate::persist;
+use crate::queue::JoinQueue;
+use crate::rate_limit::TokenBucket;
+use crate::recorder::Recorder;
+use crate::session::{Departed, Session};
+use crate::stats::Stats;
+
+// data/collection types
+use std::collections::{HashMap, HashSet};
+type AtomicPlayerDict = Arc<RwLock<HashMap<SocketAddr, Arc<RwLock<Session>>>>>;
+
+/// Shortest time between round trip times a client can report, so pinging
+/// faster than clients normally do can't flood everyone with updates
+const MIN_RTT_REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Counts of clients falling behind the broadcast channel
+#[derive(Debug, Default)]
+pub struct LagStats {
+    /// number of times a client stream lagged and had to be resynced
+    events: AtomicU64,
+    /// total number of updates skipped by lagging clients
+    skipped: AtomicU64,
+}
+
+impl LagStats {
+    /// A client stream skipped some updates, returning the lag events so far
+    fn record(&self, skipped: u64) -> u64 {
+        self.skipped.fetch_add(skipped, Ordering::Relaxed);
+        self.events.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// Game state shared between the service and the tasks it spawns
+#[derive(Debug)]
+pub struct GameState {
+    config: Config,
+    maze: ProtoMaze,
+    /// see ServerInfo::maze_id
+    maze_id: String,
+    players: AtomicPlayerDict,
+    tx: broadcast::Sender<GameUpdate>,
+    lag_stats: LagStats,
+    /// clients whose player moved since the last tick
+    moved: Mutex<HashSet<SocketAddr>>,
+    /// clients waiting for the game to have room
+    queue: Mutex<JoinQueue>,
+    /// set once the server started shutting down
+    shutting_down: AtomicBool,
+    /// players who left recently, by name
+    departed: Mutex<HashMap<String, Departed>>,
+    /// set while recording a replay
+    recorder: Mutex<Option<Recorder>>,
+    /// clients watching the game without a player
+    spectators: Mutex<HashSet<SocketAddr>>,
+    /// decides what chat messages get passed on
+    chat_filter: Mutex<Box<dyn ChatFilter>>,
+    /// stats of players in the game or who left recently, by id
+    stats: Mutex<HashMap<String, Stats>>,
+    /// ids of players whose stats changed since the last tick
+    stats_changed: Mutex<HashSet<String>>,
+    /// set once someone reached the goal of a race
+    race_won: AtomicBool,
+    /// set when best times and wins are kept across games, behind a
+    /// blocking lock since the database is only used off the async threads
+    leaderboard: Mutex<Option<Arc<std::sync::Mutex<LeaderboardDb>>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameService {
+    state: Arc<GameState>,
+}
+impl GameService {
+    #[instrument]
+    pub fn new(config: Config) -> Self {
+        let seed = config.seed.unwrap_or_else(rand::random);
+        info!(
+            "Initializing game state (mode: {:?}, maze: {:?} with seed {})",
+            config.mode, config.maze_algorithm, seed
+        );
+        let mut maze = ProtoMaze::generate(
+            config.maze_width,
+            config.maze_height,
+            config.maze_algorithm,
+            seed,
+        );
+        // only races have somewhere to get to
+        if config.mode != GameMode::Race {
+            maze.goal = None;
+        }
+        let maze_id = uuid::Uuid::new_v4().to_string();
+        Self::with_maze(config, maze, maze_id, HashMap::new(), HashMap::new())
+    }
+
+    /// Continue a saved game, whose players can rejoin within the reconnect grace
+    pub fn restore(
+        mut config: Config,
+        saved: SavedGame,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let maze = saved.maze.ok_or("Saved game has no maze")?;
+        config.mode = GameMode::from_i32(saved.mode).ok_or("Saved game has an unknown mode")?;
+        config.maze_algorithm = MazeAlgorithm::from_i32(saved.maze_algorithm)
+            .ok_or("Saved game has an unknown maze algorithm")?;
+        info!(
+            "Restoring game state (mode: {:?}, maze: {:?} with seed {}, {} players)",
+            config.mode,
+            config.maze_algorithm,
+            maze.seed,
+            saved.players.len()
+        );
+        // the grace starts over, since nobody could rejoin while we were down
+        let left_at = Instant::now();
+        let departed = saved
+            .players
+            .into_iter()
+            .map(|player| {
+                let departed = Departed {
+                    player,
+                    left_at,
+                    seen: HashSet::new(),
+                };
+                (departed.player.name.clone(), departed)
+            })
+            .collect();
+        let stats = saved
+            .stats
+            .into_iter()
+            .map(|stats| (stats.player_id.clone(), Stats::restore(stats)))
+            .collect();
+        // saves from before mazes had ids get one now
+        let maze_id = match saved.maze_id {
+            id if id.is_empty() => uuid::Uuid::new_v4().to_string(),
+            id => id,
+        };
+        Ok(Self::with_maze(config, maze, maze_id, departed, stats))
+    }
+
+    fn with_maze(
+        config: Config,
+        maze: ProtoMaze,
+        maze_id: String,
+        departed: HashMap<String, Departed>,
+        stats: HashMap<String, Stats>,
+    ) -> Self {
+        let (tx, _rx) = broadcast::channel(config.broadcast_capacity);
+        let queue = JoinQueue::new(config.max_queued);
+        let race_won = stats.values().any(|stats| stats.stats.wins != 0);
+        Self {
+            state: Arc::new(GameState {
+                config,
+                maze,
+                maze_id,
+                players: Arc::new(RwLock::new(HashMap::new())),
+                tx,
+                lag_stats: LagStats::default(),
+                moved: Mutex::new(HashSet::new()),
+                queue: Mutex::new(queue),
+                shutting_down: AtomicBool::new(false),
+                departed: Mutex::new(departed),
+                recorder: Mutex::new(None),
+                spectators: Mutex::new(HashSet::new()),
+                chat_filter: Mutex::new(Box::new(WordFilter::default())),
+                stats: Mutex::new(stats),
+                stats_changed: Mutex::new(HashSet::new()),
+                race_won: AtomicBool::new(race_won),
+                leaderboard: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Run the game loop at the configured tick rate
+    pub fn spawn_ticker(&self) -> JoinHandle<()> {
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                time::interval(Duration::from_secs(1) / state.config.tick_rate.max(1));
+            loop {
+                interval.tick().await;
+                state.tick().await;
+            }
+        })
+    }
+
+    /// Record a replay of the game from now on
+    pub async fn record_to(&self, path: &Path) -> std::io::Result<()> {
+        let config = &self.state.config;
+        let recorder = Recorder::create(path, &self.state.maze, config.mode, config.tick_rate)?;
+        *self.state.recorder.lock().await = Some(recorder);
+        Ok(())
+    }
+
+    /// Replace the chat filter, which lets everything through by default
+    pub async fn set_chat_filter(&self, filter: Box<dyn ChatFilter>) {
+        *self.state.chat_filter.lock().await = filter;
+    }
+
+    /// Keep best times and wins in leaderboard from now on
+    pub async fn set_leaderboard(&self, leaderboard: LeaderboardDb) {
+        *self.state.leaderboard.lock().await = Some(Arc::new(std::sync::Mutex::new(leaderboard)));
+    }
+
+    /// Save the game every save interval, if there is somewhere to save it
+    pub fn spawn_autosave(&self) -> Option<JoinHandle<()>> {
+        let path = self.state.config.state_file.clone()?;
+        let secs = self.state.config.save_interval_secs;
+        if secs == 0 {
+            return None;
+        }
+        let state = self.state.clone();
+        Some(tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(secs));
+            // the first tick completes immediately, and there is nothing new to save yet
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match state.save(&path).await {
+                    Ok(()) => debug!("Saved game state to {}", path.display()),
+                    Err(e) => error!("Could not save game state to {}: {}", path.display(), e),
+                }
+            }
+        }))
+    }
+
+    /// Tell every client the server is going away, saving the game first if configured to
+    pub async fn shutdown(&self) {
+        self.state.shutdown().await
+    }
+}
+
+impl GameState {
+    /// Let the client at addr join, unless others are already waiting in line.
+    /// Spectators never have to wait.
+    async fn join(
+        &self,
+        addr: SocketAddr,
+        mut join_game_request: JoinGameRequest,
+    ) -> Result<JoinGameResponse, ServerError> {
+        if self.shutting_down.load(Ordering::Relaxed) {
+            return Err(ServerError::ShuttingDown);
+        }
+        if join_game_request.spectate {
+            // spectators would see through the fog
+            if self.config.sight_radius != 0 {
+                return Err(ServerError::SpectatingDisabled);
+            }
+            return self.add_spectator(addr).await;
+        }
+        join_game_request.name = names::validate(&join_game_request.name)?;
+        if !self.queue.lock().await.is_empty() {
+            return Err(ServerError::GameFull(self.config.max_players));
+        }
+        self.add_player(addr, join_game_request).await
+    }
+
+    /// Create a player for the client at addr and tell everyone about it
+    async fn add_player(
+        &self,
+        addr: SocketAddr,
+        join_game_request: JoinGameRequest,
+    ) -> Result<JoinGameResponse, ServerError> {
+        // what a returning player had seen through the fog
+        let mut seen = HashSet::new();
+        let player_id = {
+            let mut player_dict = self.players.write().await;
+            if (*player_dict).contains_key(&addr) {
+                return Err(ServerError::AlreadyJoined(addr));
+            }
+            let max_players = self.config.max_players;
+            if max_players != 0 && (*player_dict).len() >= max_players as usize {
+                return Err(ServerError::GameFull(max_players));
+            }
+            let new_player = match self.take_departed(&join_game_request.name).await {
+                Some(departed) => {
+                    let mut player = departed.player;
+                    seen = departed.seen;
+                    info!("Player {} rejoined from {}", player.id, addr);
+                    player.alive = true;
+                    player.disconnect_reason = DisconnectReason::Quit.into();
+                    // the new client numbers its inputs from scratch
+                    player.last_input_seq = 0;
+                    player.rtt_ms = 0;
+                    player
+                }
+                None => {
+                    let mut taken = HashSet::new();
+                    for session in (*player_dict).values() {
+                        taken.insert(session.read().await.player.name.clone());
+                    }
+                    let name =
+                        names::disambiguate(&join_game_request.name, |name| taken.contains(name));
+                    if name != join_game_request.name {
+                        info!(
+                            "Name {} is taken, client at {} is {} instead",
+                            join_game_request.name, addr, name
+                        );
+                    }
+                    Player::new(name)
+                }
+            };
+            // send new player to the broadcast
+            debug!(
+                "Broadcasting new player (id: {}) for client at {}",
+                new_player.id, addr
+            );
+            self.broadcast(GameUpdate::player(new_player.clone())).await;
+            self.stats
+                .lock()
+                .await
+                .entry(new_player.id.clone())
+                .and_modify(|stats| stats.join(&new_player))
+                .or_insert_with(|| Stats::new(&new_player));
+            self.stats_changed
+                .lock()
+                .await
+                .insert(new_player.id.clone());
+            // insert into atomic player dict
+            let id = new_player.id.clone();
+            let moves = TokenBucket::new(
+                self.config.max_moves_per_sec,
+                self.config.move_burst,
+                Instant::now(),
+            );
+            let chats = TokenBucket::new(
+                self.config.max_chats_per_sec,
+                self.config.chat_burst,
+                Instant::now(),
+            );
+            let session = Session::new(new_player, moves, chats);
+            (*player_dict).insert(addr, Arc::new(RwLock::new(session)));
+            id
+        };
+        // get already-joined players (including this new one)
+        debug!("Collecting already-joined players for sending");
+        let players = self.collect_players().await;
+        let (maze, players) = self.first_sight(addr, players, seen).await;
+        Ok(JoinGameResponse {
+            player_id,
+            maze: Some(maze),
+            players,
+            spectators: self.spectators.lock().await.len() as u32,
+        })
+    }
+
+    /// Let the client at addr watch the game, and tell everyone there is one more of them
+    async fn add_spectator(&self, addr: SocketAddr) -> Result<JoinGameResponse, ServerError> {
+        if self.players.read().await.contains_key(&addr) {
+            return Err(ServerError::AlreadyJoined(addr));
+        }
+        let count = {
+            let mut spectators = self.spectators.lock().await;
+            if !spectators.insert(addr) {
+                return Err(ServerError::AlreadyJoined(addr));
+            }
+            spectators.len() as u32
+        };
+        info!("Client at {} is spectating ({} spectators)", addr, count);
+        self.broadcast(GameUpdate::spectators(count)).await;
+        Ok(JoinGameResponse {
+            player_id: String::new(),
+            maze: Some(self.maze.clone()),
+            players: self.collect_players().await,
+            spectators: count,
+        })
+    }
+
+    /// Stop sending the game to a spectator, does nothing if addr wasn't one
+    async fn remove_spectator(&self, addr: SocketAddr) {
+        let count = {
+            let mut spectators = self.spectators.lock().await;
+            if !spectators.remove(&addr) {
+                return;
+            }
+            spectators.len() as u32
+        };
+        info!(
+            "Client at {} stopped spectating ({} spectators)",
+            addr, count
+        );
+        self.broadcast(GameUpdate::spectators(count)).await;
+    }
+
+    /// Clone every player currently in the dict
+    async fn collect_players(&self) -> Vec<Player> {
+        let player_dict = self.players.read().await;
+        let mut ps: Vec<Player> = Vec::with_capacity((*player_dict).len());
+        for session_lock in player_dict.values() {
+            let session = session_lock.read().await;
+            ps.push(session.player.clone());
+        }
+        ps
+    }
+
+    /// The maze and players as the client at addr gets to see them on joining,
+    /// which with fog of war is only what is in sight or in seen
+    async fn first_sight(
+        &self,
+        addr: SocketAddr,
+        players: Vec<Player>,
+        seen: HashSet<(u32, u32)>,
+    ) -> (ProtoMaze, Vec<Player>) {
+        let radius = self.config.sight_radius;
+        let session = self.players.read().await.get(&addr).cloned();
+        let session = match session {
+            Some(session) if radius != 0 => session,
+            _ => return (self.maze.clone(), players),
+        };
+        let mut session = session.write().await;
+        let sight = Sight::new(&session.player, &self.maze, radius, &players, seen);
+        let maze = sight.masked_maze(&self.maze);
+        let players = players.iter().map(|player| sight.hide(player)).collect();
+        session.sight = Some(sight);
+        (maze, players)
+    }
+
+    /// What the client at addr gets sent for update, which
+    /// with fog of war is only what its player can see
+    async fn through_fog(&self, addr: SocketAddr, update: GameUpdate) -> Vec<GameUpdate> {
+        if self.config.sight_radius == 0 {
+            return vec![update];
+        }
+        // updates still on their way after the player left can't see anything
+        let session = match self.players.read().await.get(&addr).cloned() {
+            Some(session) => session,
+            None => return vec![fog::blind(update)],
+        };
+        let mut session = session.write().await;
+        match session.sight.as_mut() {
+            Some(sight) => sight.filter(&self.maze, update),
+            None => vec![fog::blind(update)],
+        }
+    }
+
+    /// The session of the client at addr, which has to be playing
+    async fn session(&self, addr: SocketAddr) -> Result<Arc<RwLock<Session>>, ServerError> {
+        let session = {
+            let player_dict_lock = self.players.read().await;
+            (*player_dict_lock).get(&addr).cloned()
+        };
+        match session {
+            Some(session) => Ok(session),
+            None if self.spectators.lock().await.contains(&addr) => {
+                Err(ServerError::Spectating(addr))
+            }
+            None => Err(ServerError::NotJoined(addr)),
+        }
+    }
+
+    /// Count a suspicious message from the client at addr against it,
+    /// kicking the client once it has too many strikes
+    async fn strike(
+        &self,
+        addr: SocketAddr,
+        mut session_lock: RwLockWriteGuard<'_, Session>,
+        err: ServerError,
+    ) -> ServerError {
+        session_lock.strikes += 1;
+        let strikes = session_lock.strikes;
+        warn!(
+            strikes,
+            player_id = %session_lock.player.id,
+            "Dropping suspicious message from client at {}: {}",
+            addr,
+            err
+        );
+        let max_strikes = self.config.max_strikes;
+        if max_strikes != 0 && strikes >= max_strikes {
+            drop(session_lock);
+            info!("Kicking client at {} after {} strikes", addr, strikes);
+            self.disconnect(addr, DisconnectReason::Abuse).await;
+            return ServerError::TooManyStrikes(strikes);
+        }
+        err
+    }
+
+    /// The client at addr measured its round trip time. A change is
+    /// broadcast on the next tick, and reports coming too fast are ignored.
+    async fn report_rtt(&self, addr: SocketAddr, rtt_ms: u32, now: Instant) {
+        let session = match self.session(addr).await {
+            Ok(session) => session,
+            Err(_) => return,
+        };
+        let mut session = session.write().await;
+        if session
+            .last_rtt_report
+            .is_some_and(|last| now.saturating_duration_since(last) < MIN_RTT_REPORT_INTERVAL)
+        {
+            return;
+        }
+        session.last_rtt_report = Some(now);
+        if session.player.rtt_ms != rtt_ms {
+            session.player.rtt_ms = rtt_ms;
+            trace!("Client at {} reported rtt {}ms", addr, rtt_ms);
+            self.moved.lock().await.insert(addr);
+        }
+    }
+
+    /// Validate and apply one input from the client at addr. The moved
+    /// player is broadcast on the next tick.
+    async fn apply_input(
+        &self,
+        addr: SocketAddr,
+        indir: InputDirection,
+    ) -> Result<Player, ServerError> {
+        let session = self.session(addr).await?;
+        let dir = Direction::from_i32(indir.direction)
+            .ok_or(ServerError::InvalidDirection(indir.direction))?;
+        let now = Instant::now();
+        let mut session_lock = session.write().await;
+        session_lock.touch(now);
+        let last_seq = session_lock.player.last_input_seq;
+        // seq 0 means the client doesn't number its inputs
+        let violation = if indir.seq != 0 && indir.seq <= last_seq {
+            Some(ServerError::StaleInput {
+                seq: indir.seq,
+                last_seq,
+            })
+        } else if !session_lock.moves.try_take(now) {
+            // acknowledge it without moving, so the client
+            // snaps back to where we have the player
+            session_lock.player.last_input_seq = indir.seq;
+            self.moved.lock().await.insert(addr);
+            Some(ServerError::RateLimited(addr))
+        } else {
+            None
+        };
+        if let Some(err) = violation {
+            return Err(self.strike(addr, session_lock, err).await);
+        }
+        if let Some(recorder) = self.recorder.lock().await.as_mut() {
+            recorder.record_input(&session_lock.player.id, &indir);
+        }
+        let player = &mut session_lock.player;
+        debug!(
+            "Applying player movement (player_id: {}, direction: {:?}, seq: {}) for client at {}",
+            player.id, dir, indir.seq, addr
+        );
+        let mut exit = None;
+        if player.move_if_valid(&self.maze, dir) {
+            let exited = self.maze.goal.is_some() && player.pos == self.maze.goal;
+            if exited {
+                info!("Player {} reached the goal", player.id);
+                player.reached_goal = true;
+            }
+            exit = self.record_move(player, exited).await;
+        }
+        // acknowledge the input even if it ran into a wall,
+        // so the client can drop it from its pending list
+        player.last_input_seq = indir.seq;
+        let player = player.clone();
+        self.moved.lock().await.insert(addr);
+        // the database write can be slow, so it waits until the session is free
+        drop(session_lock);
+        if let Some((time_ms, won)) = exit {
+            self.record_exit(&player.name, time_ms, won).await;
+        }
+        Ok(player)
+    }
+
+    /// Update the stats of a player who moved, tagging everyone they moved onto.
+    /// Returns their time and whether they won, if they reached the goal.
+    async fn record_move(&self, player: &Player, exited: bool) -> Option<(u64, bool)> {
+        let pos = player.pos.as_ref()?;
+        let mut stats = self.stats.lock().await;
+        let tags = stats
+            .values()
+            .filter(|other| other.stats.player_id != player.id && other.pos() == Some(pos))
+            .count() as u32;
+        let mut exit = None;
+        if let Some(stats) = stats.get_mut(&player.id) {
+            stats.record_move(pos);
+            stats.record_tags(tags);
+            if exited {
+                let won = !self.race_won.swap(true, Ordering::Relaxed);
+                stats.record_exit(won);
+                exit = Some((stats.stats.time_to_exit_ms, won));
+            }
+        }
+        drop(stats);
+        self.stats_changed.lock().await.insert(player.id.clone());
+        exit
+    }
+
+    /// Put the player called name on the leaderboard for reaching the goal, if there is one
+    async fn record_exit(&self, name: &str, time_ms: u64, won: bool) {
+        let key = self.maze.key(self.config.maze_algorithm);
+        let owned_name = name.to_string();
+        let res = self
+            .with_leaderboard(move |leaderboard| {
+                leaderboard.record_exit(&key, &owned_name, time_ms, won)
+            })
+            .await;
+        match res {
+            Ok(()) | Err(ServerError::LeaderboardDisabled) => {}
+            Err(e) => error!("Could not record {} on the leaderboard: {}", name, e),
+        }
+    }
+
+    /// Run f on the leaderboard database, on a thread where it may block
+    async fn with_leaderboard<T, F>(&self, f: F) -> Result<T, ServerError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&LeaderboardDb) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let leaderboard = self
+            .leaderboard
+            .lock()
+            .await
+            .clone()
+            .ok_or(ServerError::LeaderboardDisabled)?;
+        task::spawn_blocking(move || {
+            // the connection is still usable if another query panicked
+            let leaderboard = leaderboard
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            f(&leaderboard)
+        })
+        .await
+        .map_err(|e| ServerError::Leaderboard(e.to_string()))?
+        .map_err(|e| ServerError::Leaderboard(e.to_string()))
+    }
+
+    /// Everyone's stats, best first
+    async fn scoreboard(&self) -> Vec<PlayerStats> {
+        let mut players: Vec<PlayerStats> = self
+            .stats
+            .lock()
+            .await
+            .values()
+            .map(|stats| stats.stats.clone())
+            .collect();
+        players.sort_by(PlayerStats::rank);
+        players
+    }
+
+    /// A page of the leaderboard asked for in request
+    async fn leaderboard(&self, request: LeaderboardRequest) -> Result<Leaderboard, ServerError> {
+        let limit = match request.limit {
+            0 => leaderboard::MAX_PAGE_SIZE,
+            limit => limit.min(leaderboard::MAX_PAGE_SIZE),
+        };
+        let offset = request.offset;
+        let kind = LeaderboardKind::from_i32(request.kind);
+        // the seed of the maze being played is kept from players in the fog,
+        // since they could generate the whole maze again from it
+        let hide_seed = request.maze.is_none() && self.config.sight_radius != 0;
+        let maze = request
+            .maze
+            .unwrap_or_else(|| self.maze.key(self.config.maze_algorithm));
+        let mut page = self
+            .with_leaderboard(move |leaderboard| match kind {
+                Some(LeaderboardKind::Wins) => leaderboard.wins(offset, limit),
+                _ => leaderboard.best_times(&maze, offset, limit),
+            })
+            .await?;
+        if let Some(maze) = page.maze.as_mut().filter(|_| hide_seed) {
+            maze.seed = 0;
+        }
+        Ok(page)
+    }
+
+    /// Check a chat message from the client at addr and send it to everyone.
+    /// Returns None if there was nothing left to send after filtering.
+    async fn apply_chat(
+        &self,
+        addr: SocketAddr,
+        text: String,
+    ) -> Result<Option<ChatMessage>, ServerError> {
+        let session = self.session(addr).await?;
+        let max_len = self.config.max_chat_len;
+        if max_len == 0 {
+            return Err(ServerError::ChatDisabled);
+        }
+        let text = chat::sanitize(&text);
+        let now = Instant::now();
+        let mut session_lock = session.write().await;
+        session_lock.touch(now);
+        let violation = if text.chars().count() > max_len as usize {
+            Some(ServerError::ChatTooLong(max_len))
+        } else if !session_lock.chats.try_take(now) {
+            Some(ServerError::ChatRateLimited(addr))
+        } else {
+            None
+        };
+        if let Some(err) = violation {
+            return Err(self.strike(addr, session_lock, err).await);
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+        let text = match self.chat_filter.lock().await.filter(&text) {
+            Some(text) => text,
+            None => {
+                debug!("Chat filter dropped a message from client at {}", addr);
+                return Ok(None);
+            }
+        };
+        let message = ChatMessage {
+            player_id: session_lock.player.id.clone(),
+            name: session_lock.player.name.clone(),
+            text,
+        };
+        drop(session_lock);
+        debug!("Broadcasting chat message from client at {}", addr);
+        self.broadcast(GameUpdate::chat(message.clone())).await;
+        Ok(Some(message))
+    }
+
+    /// Remove the player of the client at addr and tell everyone why.
+    /// Every way of leaving the game goes through here.
+    async fn disconnect(&self, addr: SocketAddr, reason: DisconnectReason) -> Option<Player> {
+        let session = {
+            let mut player_dict_lock = self.players.write().await;
+            (*player_dict_lock).remove(&addr)?
+        };
+        let (mut player, sight) = {
+            let mut session = session.write().await;
+            (session.player.clone(), session.sight.take())
+        };
+        player.alive = false;
+        player.disconnect_reason = reason.into();
+        if let Some(stats) = self.stats.lock().await.get_mut(&player.id) {
+            stats.leave();
+        }
+        debug!("Broadcasting client death ({:?}) at {}", reason, addr);
+        self.broadcast(GameUpdate::player(player.clone())).await;
+        // kicked players don't get to keep their progress
+        let can_rejoin = matches!(reason, DisconnectReason::Quit | DisconnectReason::Shutdown);
+        if can_rejoin && self.config.reconnect_grace_secs != 0 {
+            let departed = Departed {
+                player: player.clone(),
+                left_at: Instant::now(),
+                seen: sight.map(Sight::into_seen).unwrap_or_default(),
+            };
+            self.departed
+                .lock()
+                .await
+                .insert(player.name.clone(), departed);
+        }
+        Some(player)
+    }
+
+    /// Warn players who have been idle for a while, and kick
+    /// those who have been idle for too long
+    async fn check_idle(&self, now: Instant) {
+        let timeout = self.config.idle_timeout_secs;
+        if timeout == 0 {
+            return;
+        }
+        let warn_after = Duration::from_secs(timeout - self.config.idle_warning_secs);
+        let kick_after = Duration::from_secs(timeout);
+        let mut to_kick = Vec::new();
+        {
+            let player_dict = self.players.read().await;
+            for (addr, session) in (*player_dict).iter() {
+                let mut session = session.write().await;
+                let idle = now.saturating_duration_since(session.last_active);
+                if idle >= kick_after {
+                    to_kick.push(*addr);
+                } else if idle >= warn_after && !session.idle_warned {
+                    session.idle_warned = true;
+                    let seconds_left = (kick_after - idle).as_secs() as u32;
+                    debug!("Warning idle client at {}", addr);
+                    self.broadcast(GameUpdate::idle_warning(
+                        session.player.id.clone(),
+                        seconds_left,
+                    ))
+                    .await;
+                }
+            }
+        }
+        for addr in to_kick {
+            info!("Kicking idle client at {}", addr);
+            self.disconnect(addr, DisconnectReason::Idle).await;
+        }
+    }
+
+    /// Send an update to every client, and into the replay if we are recording
+    async fn broadcast(&self, update: GameUpdate) {
+        if let Some(recorder) = self.recorder.lock().await.as_mut() {
+            recorder.record_update(&update);
+        }
+        // We will get an error here if there are no clients listening.
+        // We can ignore it, since it just means there are no
+        // recievers.
+        self.tx.send(update).ok();
+    }
+
+    /// Let waiting clients in while there is room,
+    /// and tell the rest how far along they are
+    async fn admit_queued(&self) {
+        let mut queue = self.queue.lock().await;
+        while let Some(client) = queue.pop() {
+            match self.add_player(client.addr, client.request.clone()).await {
+                Ok(response) => {
+                    info!("Letting in queued client at {}", client.addr);
+                    // the client gave up just as it got in, or stopped reading,
+                    // which we can't wait for with the game loop stalled
+                    if client
+                        .tx
+                        .try_send(Ok(QueueUpdate::joined(response)))
+                        .is_err()
+                    {
+                        self.disconnect(client.addr, DisconnectReason::Quit).await;
+                    }
+                }
+                Err(ServerError::GameFull(_)) => {
+                    queue.push_front(client);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Dropping queued client at {}: {}", client.addr, e);
+                    client.tx.try_send(Err(e.into())).ok();
+                }
+            }
+        }
+        queue.notify_positions();
+    }
+
+    /// The player who left under this name, if they did so recently enough to come back
+    async fn take_departed(&self, name: &str) -> Option<Departed> {
+        let departed = self.departed.lock().await.remove(name)?;
+        let grace = Duration::from_secs(self.config.reconnect_grace_secs);
+        (departed.left_at.elapsed() < grace).then_some(departed)
+    }
+
+    /// Stop taking players, remove everyone and save the game
+    async fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        self.queue.lock().await.close(ServerError::ShuttingDown);
+        let addrs: Vec<SocketAddr> = self.players.read().await.keys().copied().collect();
+        info!("Disconnecting {} players", addrs.len());
+        for addr in addrs {
+            self.disconnect(addr, DisconnectReason::Shutdown).await;
+        }
+        // spectator streams are told about the shutdown and end on the first update they get after this
+        self.spectators.lock().await.clear();
+        self.broadcast(GameUpdate::spectators(0)).await;
+        self.stop_recording().await;
+        if let Some(path) = &self.config.state_file {
+            match self.save(path).await {
+                Ok(()) => info!("Saved game state to {}", path.display()),
+                Err(e) => error!("Could not save game state to {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Finish the replay, if we are recording one, waiting until it is all written
+    async fn stop_recording(&self) {
+        let recorder = match self.recorder.lock().await.take() {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        match task::spawn_blocking(move || recorder.finish()).await {
+            Ok(Ok(())) => info!("Finished the replay"),
+            Ok(Err(e)) => error!("Could not finish the replay: {}", e),
+            Err(e) => error!("Could not finish the replay: {}", e),
+        }
+    }
+
+    /// Write the maze, settings and players (including recently departed ones) to path
+    async fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut players = self.collect_players().await;
+        players.extend(
+            self.departed
+                .lock()
+                .await
+                .values()
+                .map(|departed| departed.player.clone()),
+        );
+        let saved = SavedGame {
+            maze: Some(self.maze.clone()),
+            players,
+            saved_at_ms: now_millis(),
+            mode: self.config.mode.into(),
+            maze_algorithm: self.config.maze_algorithm.into(),
+            stats: self.scoreboard().await,
+            maze_id: self.maze_id.clone(),
+        };
+        // every lock is released by now, and the write happens where it may block
+        let path = path.to_path_buf();
+        task::spawn_blocking(move || persist::save(&path, &saved)).await?
+    }
+
+    /// Broadcast every player that moved and all stats that changed
+    /// since the last tick, then deal with idle and waiting players
+    async fn tick(&self) {
+        let moved: Vec<SocketAddr> = self.moved.lock().await.drain().collect();
+        {
+            let player_dict = self.players.read().await;
+            for addr in moved {
+                // players who left were already broadcast as dead
+                if let Some(session) = (*player_dict).get(&addr) {
+                    let session = session.read().await;
+                    trace!("Broadcasting movement of client at {}", addr);
+                    self.broadcast(GameUpdate::player(session.player.clone()))
+                        .await;
+                }
+            }
+        }
+        let changed: Vec<String> = self.stats_changed.lock().await.drain().collect();
+        {
+            let stats = self.stats.lock().await;
+            for id in changed {
+                if let Some(stats) = stats.get(&id) {
+                    self.broadcast(GameUpdate::stats(stats.stats.clone())).await;
+                }
+            }
+        }
+        let now = Instant::now();
+        self.check_idle(now).await;
+        let grace = Duration::from_secs(self.config.reconnect_grace_secs);
+        let mut departed = self.departed.lock().await;
+        departed.retain(|_, departed| now.saturating_duration_since(departed.left_at) < grace);
+        // stats are kept as long as their player could come back
+        let departed_ids: HashSet<&str> = departed
+            .values()
+            .map(|departed| departed.player.id.as_str())
+            .collect();
+        self.stats
+            .lock()
+            .await
+            .retain(|id, stats| stats.pos().is_some() || departed_ids.contains(id.as_str()));
+        drop(departed);
+        self.admit_queued().await;
+
+        let mut recorder = self.recorder.lock().await;
+        if let Some(Err(e)) = recorder.as_mut().map(Recorder::finish_tick) {
+            error!("Could not write replay, recording stopped: {}", e);
+            *recorder = None;
+        }
+    }
+}
+
+/// addr is how we identify clients, so every request needs one
+fn remote_addr<T>(request: &Request<T>) -> Result<SocketAddr, ServerError> {
+    request.remote_addr().ok_or(ServerError::MissingRemoteAddr)
+}
+
+#[tonic::async_trait]
+impl Game for GameService {
+    #[instrument(skip(self))]
+    async fn get_server_info(
+        &self,
+        _request: Request<ServerInfoRequest>,
+    ) -> Result<Response<ServerInfo>, Status> {
+        let config = &self.state.config;
+        let player_count = self.state.players.read().await.len() as u32;
+        let spectator_count = self.state.spectators.lock().await.len() as u32;
+        let mut features = vec![
+            features::INPUT_SEQ,
+            features::SNAPSHOTS,
+            features::PING,
+            features::IDLE_KICK,
+            features::SCOREBOARD,
+        ];
+        if config.sight_radius == 0 {
+            features.push(features::SPECTATE);
+        } else {
+            features.push(features::FOG);
+        }
+        if config.max_chat_len != 0 {
+            features.push(features::CHAT);
+        }
+        // there's no telling an autopilot's moves apart from a player's,
+        // so this is up to the client to honor
+        if config.assist_modes.contains(&config.mode) {
+            features.push(features::ASSIST);
+        }
+        if self.state.leaderboard.lock().await.is_some() {
+            features.push(features::LEADERBOARD);
+        }
+        // a queue is pointless if the game never fills up
+        if config.max_players != 0 && config.max_queued != 0 {
+            features.push(features::JOIN_QUEUE);
+        }
+        Ok(Response::new(ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            server_build: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            mode: config.mode.into(),
+            maze_algorithm: config.maze_algorithm.into(),
+            player_count,
+            max_players: config.max_players,
+            features: features.iter().map(|f| f.to_string()).collect(),
+            spectator_count,
+            max_chat_len: config.max_chat_len,
+            sight_radius: config.sight_radius,
+            lag_events: self.state.lag_stats.events.load(Ordering::Relaxed),
+            lag_skipped: self.state.lag_stats.skipped.load(Ordering::Relaxed),
+            maze_id: self.state.maze_id.clone(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn connect_player(
+        &self,
+        request: Request<JoinGameRequest>,
+    ) -> Result<Response<JoinGameResponse>, Status> {
+        let addr = remote_addr(&request)?;
+        info!("Recieved connect_player request from client at {}", addr);
+        let response = self
+            .state
+            .join(addr, request.into_inner())
+            .await
+            .map_err(|e| {
+                warn!("Rejecting connect_player request: {}", e);
+                e
+            })?;
+        // return response
+        debug!("Returning connect_player response to client at {}", addr);
+        Ok(Response::new(response))
+    }
+
+    type JoinQueueStream = ReceiverStream<Result<QueueUpdate, Status>>;
+    #[instrument(skip(self))]
+    async fn join_queue(
+        &self,
+        request: Request<JoinGameRequest>,
+    ) -> Result<Response<Self::JoinQueueStream>, Status> {
+        let addr = remote_addr(&request)?;
+        info!("Recieved join_queue request from client at {}", addr);
+        if self.state.shutting_down.load(Ordering::Relaxed) {
+            return Err(ServerError::ShuttingDown.into());
+        }
+        if self.state.players.read().await.contains_key(&addr) {
+            warn!("Rejecting join_queue request from client at {}", addr);
+            return Err(ServerError::AlreadyJoined(addr).into());
+        }
+        let mut join_game_request = request.into_inner();
+        join_game_request.name = names::validate(&join_game_request.name).map_err(|e| {
+            warn!("Rejecting join_queue request: {}", e);
+            e
+        })?;
+        // the client is let in on a later tick, even if there is room right now
+        let rx = self
+            .state
+            .queue
+            .lock()
+            .await
+            .push(addr, join_game_request)
+            .map_err(|e| {
+                warn!("Rejecting join_queue request: {}", e);
+                e
+            })?;
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type StreamGameStream =
+        Pin<Box<dyn futures_core::Stream<Item = Result<GameUpdate, Status>> + Send + 'static>>;
+    #[instrument(skip(self))]
+    async fn stream_game(
+        &self,
+        request: Request<Streaming<ClientMessage>>,
+    ) -> Result<Response<Self::StreamGameStream>, Status> {
+        let addr = remote_addr(&request)?;
+        info!("Recieved stream_game request from client at {}", addr);
+        // spectators have no player of their own
+        let player_id = match self.state.players.read().await.get(&addr) {
+            Some(session) => Some(session.read().await.player.id.clone()),
+            None if self.state.spectators.lock().await.contains(&addr) => None,
+            None => {
+                warn!("Rejecting stream_game request from client at {}", addr);
+                return Err(ServerError::NotJoined(addr).into());
+            }
+        };
+        let spectating = player_id.is_none();
+        let mut message_stream = request.into_inner();
+        // used to end the client's stream with an error if its input is invalid
+        let (status_tx, status_rx) = oneshot::channel::<Status>();
+
+        // clones for moving into thread
+        {
+            let state = self.state.clone();
+            // read from client stream and apply their movement, which gets
+            // broadcast on the next tick, and their chat, which is sent right away
+            tokio::spawn(async move {
+                while let Ok(Some(message)) = TryStreamExt::try_next(&mut message_stream).await {
+                    let res = match message.message {
+                        Some(client_message::Message::Input(indir)) => {
+                            state.apply_input(addr, indir).await.map(|_| ())
+                        }
+                        Some(client_message::Message::Chat(text)) => {
+                            state.apply_chat(addr, text).await.map(|_| ())
+                        }
+                        None => Ok(()),
+                    };
+                    match res {
+                        Ok(()) => {}
+                        // already logged, the client can keep playing
+                        Err(e) if e.is_recoverable() => {}
+                        Err(e) => {
+                            warn!("Rejecting input from client at {}: {}", addr, e);
+                            status_tx.send(e.into()).ok();
+                            break;
+                        }
+                    }
+                }
+                info!(
+                    "Incoming stream from client at {} ended. Assuming client disconnected.",
+                    addr
+                );
+                if spectating {
+                    state.remove_spectator(addr).await;
+                } else {
+                    // does nothing if the player was already kicked
+                    state.disconnect(addr, DisconnectReason::Quit).await;
+                }
+            });
+        }
+
+        let broadcast_sub = self.state.tx.subscribe();
+        let state = self.state.clone();
+        let updates = BroadcastStream::new(broadcast_sub)
+            .then(move |res| {
+                let state = state.clone();
+                async move {
+                    let update = match res {
+                        Ok(update) => update,
+                        // the client missed some updates, so instead of
+                        // dropping its stream we send it the full state
+                        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                            let events = state.lag_stats.record(skipped);
+                            warn!(
+                                "Client at {} lagged behind by {} updates, resyncing (lag events so far: {})",
+                                addr, skipped, events
+                            );
+                            GameUpdate::snapshot(state.collect_players().await)
+                        }
+                    };
+                    let updates = state.through_fog(addr, update).await;
+                    stream::iter(updates.into_iter().map(Ok))
+                }
+            })
+            .flatten();
+        // a dropped sender just means the input stream ended normally
+        let errors = stream::once(status_rx).filter_map(|res| async move { res.ok().map(Err) });
+        // end the stream once the client has been told it left the game,
+        // or for spectators, once they have been told the server is shutting down
+        let state = self.state.clone();
+        let output = stream::select(updates, errors).scan(false, move |done, res| {
+            if *done {
+                return futures_util::future::ready(None);
+            }
+            if spectating && state.shutting_down.load(Ordering::Relaxed) {
+                *done = true;
+                return futures_util::future::ready(Some(Err(ServerError::ShuttingDown.into())));
+            }
+            if let Ok(GameUpdate {
+                update: Some(game_update::Update::Player(player)),
+                ..
+            }) = &res
+            {
+                *done = !player.alive && Some(&player.id) == player_id.as_ref();
+            }
+            futures_util::future::ready(Some(res))
+        });
+        Ok(Response::new(Box::pin(output)))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_scoreboard(
+        &self,
+        _request: Request<ScoreboardRequest>,
+    ) -> Result<Response<Scoreboard>, Status> {
+        Ok(Response::new(Scoreboard {
+            players: self.state.scoreboard().await,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_leaderboard(
+        &self,
+        request: Request<LeaderboardRequest>,
+    ) -> Result<Response<Leaderboard>, Status> {
+        let page = self
+            .state
+            .leaderboard(request.into_inner())
+            .await
+            .map_err(|e| {
+                warn!("Rejecting get_leaderboard request: {}", e);
+                e
+            })?;
+        Ok(Response::new(page))
+    }
+
+    #[instrument(skip(self))]
+    async fn ping(&self, request: Request<PingRequest>) -> Result<Response<PingResponse>, Status> {
+        let addr = remote_addr(&request)?;
+        let ping_request = request.into_inner();
+        self.state
+            .report_rtt(addr, ping_request.last_rtt_ms, Instant::now())
+            .await;
+        Ok(Response::new(PingResponse {
+            client_time_ms: ping_request.client_time_ms,
+            server_time_ms: now_millis(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn game() -> GameService {
+        GameService::new(Config {
+            maze_width: 4,
+            maze_height: 4,
+            ..Config::default()
+        })
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn join_request() -> JoinGameRequest {
+        JoinGameRequest {
+            name: "tester".to_string(),
+            spectate: false,
+        }
+    }
+
+    fn input(direction: i32) -> InputDirection {
+        InputDirection { direction, seq: 1 }
+    }
+
+    /// a race along a single corridor, from the start at (1, 1) to the goal at (3, 1)
+    fn corridor_config() -> Config {
+        Config {
+            maze_width: 2,
+            maze_height: 1,
+            mode: GameMode::Race,
+            ..Config::default()
+        }
+    }
+
+    fn corridor_game() -> GameService {
+        GameService::new(corridor_config())
+    }
+
+    #[tokio::test]
+    async fn request_without_remote_addr_is_rejected() {
+        let game = game();
+        let status = game
+            .connect_player(Request::new(join_request()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+        let status = game
+            .ping(Request::new(PingRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn server_info_reports_lagging_clients() {
+        let game = game();
+        game.state.lag_stats.record(7);
+        game.state.lag_stats.record(3);
+        let info = game
+            .get_server_info(Request::new(ServerInfoRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((info.lag_events, info.lag_skipped), (2, 10));
+    }
+
+    #[tokio::test]
+    async fn rtt_reports_are_throttled_and_wait_for_the_tick() {
+        let game = game();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let now = Instant::now();
+        game.state.report_rtt(addr(1), 40, now).await;
+        game.state
+            .report_rtt(addr(1), 90, now + MIN_RTT_REPORT_INTERVAL / 2)
+            .await;
+        let session = game.state.session(addr(1)).await.unwrap();
+        assert_eq!(session.read().await.player.rtt_ms, 40);
+        assert!(game.state.moved.lock().await.contains(&addr(1)));
+
+        game.state.tick().await;
+        game.state
+            .report_rtt(addr(1), 40, now + MIN_RTT_REPORT_INTERVAL)
+            .await;
+        // unchanged, so there's nothing to broadcast
+        assert!(game.state.moved.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn joining_twice_is_rejected() {
+        let game = game();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let err = game.state.join(addr(1), join_request()).await.unwrap_err();
+        assert_eq!(err, ServerError::AlreadyJoined(addr(1)));
+        assert_eq!(Status::from(err).code(), Code::AlreadyExists);
+        assert_eq!(game.state.players.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn input_without_join_is_rejected() {
+        let game = game();
+        let err = game.state.apply_input(addr(1), input(0)).await.unwrap_err();
+        assert_eq!(err, ServerError::NotJoined(addr(1)));
+        assert_eq!(Status::from(err).code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn invalid_direction_is_rejected() {
+        let game = game();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let err = game
+            .state
+            .apply_input(addr(1), input(42))
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServerError::InvalidDirection(42));
+        assert_eq!(Status::from(err).code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn valid_input_is_acknowledged() {
+        let game = game();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let player = game.state.apply_input(addr(1), input(0)).await.unwrap();
+        assert_eq!(player.last_input_seq, 1);
+    }
+
+    #[tokio::test]
+    async fn stale_and_flooded_inputs_are_dropped() {
+        let game = GameService::new(Config {
+            max_moves_per_sec: 1,
+            move_burst: 1,
+            ..Config::default()
+        });
+        game.state.join(addr(1), join_request()).await.unwrap();
+        game.state.apply_input(addr(1), input(0)).await.unwrap();
+        let err = game.state.apply_input(addr(1), input(1)).await.unwrap_err();
+        assert_eq!(
+            err,
+            ServerError::StaleInput {
+                seq: 1,
+                last_seq: 1
+            }
+        );
+        assert!(err.is_recoverable());
+
+        let err = game
+            .state
+            .apply_input(
+                addr(1),
+                InputDirection {
+                    direction: 1,
+                    seq: 2,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServerError::RateLimited(addr(1)));
+        // acknowledged anyway, so the client can reconcile
+        let session = game.state.players.read().await[&addr(1)].clone();
+        let session = session.read().await;
+        assert_eq!(session.player.last_input_seq, 2);
+        assert_eq!(session.strikes, 2);
+    }
+
+    #[tokio::test]
+    async fn repeat_offenders_are_kicked() {
+        let game = GameService::new(Config {
+            max_strikes: 2,
+            ..Config::default()
+        });
+        let mut rx = game.state.tx.subscribe();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        rx.recv().await.unwrap();
+        game.state.apply_input(addr(1), input(0)).await.unwrap();
+        game.state.apply_input(addr(1), input(0)).await.unwrap_err();
+        let err = game.state.apply_input(addr(1), input(0)).await.unwrap_err();
+        assert_eq!(err, ServerError::TooManyStrikes(2));
+        assert!(!err.is_recoverable());
+        assert!(game.state.players.read().await.is_empty());
+        match rx.recv().await.unwrap().update {
+            Some(game_update::Update::Player(player)) => {
+                assert_eq!(player.disconnect_reason(), DisconnectReason::Abuse);
+            }
+            other => panic!("Expected a kicked player, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn joining_a_full_game_is_rejected() {
+        let game = GameService::new(Config {
+            max_players: 1,
+            ..Config::default()
+        });
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let err = game.state.join(addr(2), join_request()).await.unwrap_err();
+        assert_eq!(err, ServerError::GameFull(1));
+        assert_eq!(Status::from(err).code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn queued_client_joins_when_a_slot_opens() {
+        let game = GameService::new(Config {
+            max_players: 1,
+            max_queued: 2,
+            ..Config::default()
+        });
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let mut rx = game
+            .state
+            .queue
+            .lock()
+            .await
+            .push(addr(2), join_request())
+            .unwrap();
+        let update = rx.recv().await.unwrap().unwrap();
+        assert_eq!(update, QueueUpdate::position(1));
+
+        // nobody jumps the line, even once a slot opens
+        game.state.disconnect(addr(1), DisconnectReason::Quit).await;
+        let err = game.state.join(addr(3), join_request()).await.unwrap_err();
+        assert_eq!(err, ServerError::GameFull(1));
+
+        game.state.admit_queued().await;
+        match rx.recv().await.unwrap().unwrap().update {
+            Some(queue_update::Update::Joined(response)) => {
+                assert!(response.players.iter().any(|p| p.id == response.player_id));
+            }
+            other => panic!("Expected to be let in, got {:?}", other),
+        }
+        assert!(game.state.players.read().await.contains_key(&addr(2)));
+        assert!(game.state.queue.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn queue_has_a_limit() {
+        let game = game();
+        let err = game
+            .state
+            .queue
+            .lock()
+            .await
+            .push(addr(1), join_request())
+            .unwrap_err();
+        assert_eq!(err, ServerError::QueueDisabled);
+
+        let game = GameService::new(Config {
+            max_players: 1,
+            max_queued: 1,
+            ..Config::default()
+        });
+        let mut queue = game.state.queue.lock().await;
+        let _rx = queue.push(addr(1), join_request()).unwrap();
+        let err = queue.push(addr(1), join_request()).unwrap_err();
+        assert_eq!(err, ServerError::AlreadyQueued(addr(1)));
+        let err = queue.push(addr(2), join_request()).unwrap_err();
+        assert_eq!(err, ServerError::QueueFull(1));
+        assert_eq!(Status::from(err).code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn shutdown_saves_and_removes_everyone() {
+        let path = std::env::temp_dir().join(format!("mazeio-test-{}.state", uuid::Uuid::new_v4()));
+        let game = GameService::new(Config {
+            state_file: Some(path.clone()),
+            ..Config::default()
+        });
+        let mut rx = game.state.tx.subscribe();
+        let player_id = game
+            .state
+            .join(addr(1), join_request())
+            .await
+            .unwrap()
+            .player_id;
+        rx.recv().await.unwrap();
+
+        game.shutdown().await;
+        match rx.recv().await.unwrap().update {
+            Some(game_update::Update::Player(player)) => {
+                assert!(!player.alive);
+                assert_eq!(player.disconnect_reason(), DisconnectReason::Shutdown);
+            }
+            other => panic!("Expected a disconnected player, got {:?}", other),
+        }
+        let err = game.state.join(addr(2), join_request()).await.unwrap_err();
+        assert_eq!(err, ServerError::ShuttingDown);
+
+        let saved = persist::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(saved.maze.as_ref(), Some(&game.state.maze));
+        assert_eq!(saved.players.len(), 1);
+        assert_eq!(saved.players[0].id, player_id);
+    }
+
+    #[tokio::test]
+    async fn saved_game_is_restored() {
+        let path = std::env::temp_dir().join(format!("mazeio-test-{}.state", uuid::Uuid::new_v4()));
+        let config = Config {
+            mode: GameMode::Race,
+            state_file: Some(path.clone()),
+            ..Config::default()
+        };
+        let game = GameService::new(config.clone());
+        let player_id = game
+            .state
+            .join(addr(1), join_request())
+            .await
+            .unwrap()
+            .player_id;
+        game.shutdown().await;
+
+        let saved = persist::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).ok();
+        let restored = GameService::restore(
+            Config {
+                mode: GameMode::Explore,
+                ..config
+            },
+            saved,
+        )
+        .unwrap();
+        assert_eq!(restored.state.config.mode, GameMode::Race);
+        assert_eq!(restored.state.maze, game.state.maze);
+        assert_eq!(restored.state.maze_id, game.state.maze_id);
+        assert_eq!(restored.state.scoreboard().await[0].player_id, player_id);
+        let response = restored.state.join(addr(2), join_request()).await.unwrap();
+        assert_eq!(response.player_id, player_id);
+    }
+
+    #[tokio::test]
+    async fn names_are_validated_and_made_unique() {
+        let game = game();
+        let named = |name: &str| JoinGameRequest {
+            name: name.to_string(),
+            spectate: false,
+        };
+        let status = Status::from(
+            game.state
+                .join(addr(1), named("\x1b[2J"))
+                .await
+                .unwrap_err(),
+        );
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().contains("can't contain"));
+
+        let first = game.state.join(addr(1), named(" ann ")).await.unwrap();
+        let second = game.state.join(addr(2), named("ann")).await.unwrap();
+        let name_of = |response: &JoinGameResponse, id: &str| {
+            response
+                .players
+                .iter()
+                .find(|player| player.id == id)
+                .map(|player| player.name.clone())
+        };
+        assert_eq!(name_of(&first, &first.player_id).as_deref(), Some("ann"));
+        assert_eq!(
+            name_of(&second, &second.player_id).as_deref(),
+            Some("ann-2")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejoining_under_the_same_name_resumes() {
+        let game = game();
+        let player_id = game
+            .state
+            .join(addr(1), join_request())
+            .await
+            .unwrap()
+            .player_id;
+        game.state.apply_input(addr(1), input(0)).await.unwrap();
+        let left = game
+            .state
+            .disconnect(addr(1), DisconnectReason::Quit)
+            .await
+            .unwrap();
+
+        let response = game.state.join(addr(2), join_request()).await.unwrap();
+        assert_eq!(response.player_id, player_id);
+        let player = response.players.iter().find(|p| p.id == player_id).unwrap();
+        assert!(player.alive);
+        assert_eq!(player.pos, left.pos);
+        assert_eq!(player.last_input_seq, 0);
+
+        // kicked players start over
+        game.state
+            .disconnect(addr(2), DisconnectReason::Idle)
+            .await
+            .unwrap();
+        let response = game.state.join(addr(3), join_request()).await.unwrap();
+        assert_ne!(response.player_id, player_id);
+    }
+
+    #[tokio::test]
+    async fn ticks_are_recorded_to_the_replay() {
+        let path =
+            std::env::temp_dir().join(format!("mazeio-test-{}.replay", uuid::Uuid::new_v4()));
+        let game = game();
+        game.record_to(&path).await.unwrap();
+        let player_id = game
+            .state
+            .join(addr(1), join_request())
+            .await
+            .unwrap()
+            .player_id;
+        game.state.tick().await;
+        // nothing happened, so nothing is written
+        game.state.tick().await;
+        game.state.apply_input(addr(1), input(0)).await.unwrap();
+        game.state.tick().await;
+        game.state.stop_recording().await;
+
+        let (header, frames) = replay::read(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(header.maze.as_ref(), Some(&game.state.maze));
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].tick, 1);
+        assert_eq!(frames[0].players[0].id, player_id);
+        assert!(frames[0].inputs.is_empty());
+        assert_eq!(frames[1].tick, 3);
+        assert_eq!(frames[1].inputs[0].player_id, player_id);
+        assert_eq!(frames[1].inputs[0].input, Some(input(0)));
+        assert_eq!(frames[1].players[0].last_input_seq, 1);
+    }
+
+    #[tokio::test]
+    async fn spectators_watch_without_a_player() {
+        let game = game();
+        let mut rx = game.state.tx.subscribe();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        rx.recv().await.unwrap();
+        let response = game
+            .state
+            .join(
+                addr(2),
+                JoinGameRequest {
+                    spectate: true,
+                    ..join_request()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(response.player_id.is_empty());
+        assert_eq!(response.players.len(), 1);
+        assert_eq!(response.spectators, 1);
+        assert_eq!(
+            rx.recv().await.unwrap().update,
+            Some(game_update::Update::Spectators(1))
+        );
+        assert_eq!(game.state.players.read().await.len(), 1);
+
+        let err = game.state.apply_input(addr(2), input(0)).await.unwrap_err();
+        assert_eq!(err, ServerError::Spectating(addr(2)));
+        assert!(!err.is_recoverable());
+
+        game.state.remove_spectator(addr(2)).await;
+        assert_eq!(
+            rx.recv().await.unwrap().update,
+            Some(game_update::Update::Spectators(0))
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_is_checked_filtered_and_broadcast() {
+        let game = GameService::new(Config {
+            max_chat_len: 10,
+            chat_burst: 2,
+            ..Config::default()
+        });
+        game.set_chat_filter(Box::new(WordFilter::new(["darn".to_string()])))
+            .await;
+        let mut rx = game.state.tx.subscribe();
+        let player_id = game
+            .state
+            .join(addr(1), join_request())
+            .await
+            .unwrap()
+            .player_id;
+        rx.recv().await.unwrap();
+
+        let message = game
+            .state
+            .apply_chat(addr(1), " darn it\n".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.player_id, player_id);
+        assert_eq!(message.text, "**** it");
+        assert_eq!(
+            rx.recv().await.unwrap().update,
+            Some(game_update::Update::Chat(message))
+        );
+
+        let err = game
+            .state
+            .apply_chat(addr(1), "a".repeat(11))
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServerError::ChatTooLong(10));
+        game.state
+            .apply_chat(addr(1), "hi".to_string())
+            .await
+            .unwrap();
+        let err = game
+            .state
+            .apply_chat(addr(1), "hi".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServerError::ChatRateLimited(addr(1)));
+        assert!(err.is_recoverable());
+        let session = game.state.players.read().await[&addr(1)].clone();
+        assert_eq!(session.read().await.strikes, 2);
+    }
+
+    #[tokio::test]
+    async fn reaching_the_goal_finishes_a_race() {
+        let game = corridor_game();
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let moves = [Direction::Right, Direction::Right, Direction::Left];
+        let mut inputs = moves.iter().zip(1..).map(|(dir, seq)| InputDirection {
+            direction: *dir as i32,
+            seq,
+        });
+        let mut apply = || game.state.apply_input(addr(1), inputs.next().unwrap());
+        apply().await.unwrap();
+        let player = apply().await.unwrap();
+        assert_eq!(player.pos, Some(Position::new(3, 1)));
+        assert!(player.reached_goal);
+        // and can't move any more
+        let player = apply().await.unwrap();
+        assert_eq!(player.pos, Some(Position::new(3, 1)));
+    }
+
+    #[tokio::test]
+    async fn stats_track_moves_tags_and_the_winner() {
+        let game = corridor_game();
+        let mut rx = game.state.tx.subscribe();
+        let first = game.state.join(addr(1), join_request()).await.unwrap();
+        let second = game.state.join(addr(2), join_request()).await.unwrap();
+        let right = |seq| InputDirection {
+            direction: Direction::Right as i32,
+            seq,
+        };
+        game.state.apply_input(addr(1), right(1)).await.unwrap();
+        game.state.apply_input(addr(2), right(1)).await.unwrap();
+        game.state.apply_input(addr(1), right(2)).await.unwrap();
+        game.state.apply_input(addr(2), right(2)).await.unwrap();
+
+        let scoreboard = game.state.scoreboard().await;
+        assert_eq!(scoreboard[0].player_id, first.player_id);
+        assert_eq!(scoreboard[0].wins, 1);
+        assert_eq!(scoreboard[0].tags, 0);
+        assert_eq!(scoreboard[1].player_id, second.player_id);
+        assert_eq!(scoreboard[1].wins, 0);
+        assert_ne!(scoreboard[1].time_to_exit_ms, 0);
+        // caught up with the first player twice
+        assert_eq!(scoreboard[1].tags, 2);
+        for stats in &scoreboard {
+            assert_eq!(stats.moves, 2);
+            assert_eq!(stats.cells_explored, 3);
+        }
+
+        game.state.tick().await;
+        let mut streamed = Vec::new();
+        while let Ok(update) = rx.try_recv() {
+            if let Some(game_update::Update::Stats(stats)) = update.update {
+                streamed.push(stats);
+            }
+        }
+        streamed.sort_by(PlayerStats::rank);
+        assert_eq!(streamed, scoreboard);
+    }
+
+    #[tokio::test]
+    async fn finishing_a_race_goes_on_the_leaderboard() {
+        let game = corridor_game();
+        let request = LeaderboardRequest::default();
+        assert_eq!(
+            game.state.leaderboard(request.clone()).await,
+            Err(ServerError::LeaderboardDisabled)
+        );
+        game.set_leaderboard(LeaderboardDb::open(Path::new(":memory:")).unwrap())
+            .await;
+        game.state.join(addr(1), join_request()).await.unwrap();
+        for seq in 1..=2 {
+            let right = InputDirection {
+                direction: Direction::Right as i32,
+                seq,
+            };
+            game.state.apply_input(addr(1), right).await.unwrap();
+        }
+
+        let times = game.state.leaderboard(request).await.unwrap();
+        assert_eq!(
+            times.maze,
+            Some(game.state.maze.key(game.state.config.maze_algorithm))
+        );
+        assert_eq!(times.entries.len(), 1);
+        assert_eq!(times.entries[0].name, "tester");
+        assert_ne!(times.entries[0].time_ms, 0);
+        let wins = game
+            .state
+            .leaderboard(LeaderboardRequest {
+                kind: LeaderboardKind::Wins.into(),
+                ..LeaderboardRequest::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(wins.entries[0].wins, 1);
+    }
+
+    #[tokio::test]
+    async fn fog_of_war_hides_what_players_cant_see() {
+        let game = GameService::new(Config {
+            sight_radius: 1,
+            ..corridor_config()
+        });
+        let spectate = JoinGameRequest {
+            spectate: true,
+            ..join_request()
+        };
+        assert_eq!(
+            game.state.join(addr(3), spectate).await,
+            Err(ServerError::SpectatingDisabled)
+        );
+        let response = game.state.join(addr(1), join_request()).await.unwrap();
+        let maze = response.maze.unwrap();
+        assert_eq!(maze.get(2, 1), CellType::Open);
+        assert_eq!(maze.get(3, 1), CellType::Unknown);
+        assert_eq!(maze.goal, None);
+        assert_eq!(maze.seed, 0);
+
+        game.state.join(addr(2), join_request()).await.unwrap();
+        for seq in 1..=2 {
+            let right = InputDirection {
+                direction: Direction::Right as i32,
+                seq,
+            };
+            game.state.apply_input(addr(2), right).await.unwrap();
+        }
+        let other = game
+            .state
+            .session(addr(2))
+            .await
+            .unwrap()
+            .read()
+            .await
+            .player
+            .clone();
+        let seen = game
+            .state
+            .through_fog(addr(1), GameUpdate::player(other.clone()))
+            .await;
+        match &seen[..] {
+            [GameUpdate {
+                update: Some(game_update::Update::Player(player)),
+                ..
+            }] => {
+                assert_eq!(player.id, other.id);
+                assert_eq!(player.pos, None);
+            }
+            other => panic!("expected just the hidden player, got {:?}", other),
+        }
+        // while the one who moved gets to see the goal
+        let seen = game
+            .state
+            .through_fog(addr(2), GameUpdate::player(other))
+            .await;
+        let goal = seen.iter().find_map(|update| match &update.update {
+            Some(game_update::Update::Revealed(revealed)) => revealed.goal.clone(),
+            _ => None,
+        });
+        assert_eq!(goal, Some(Position::new(3, 1)));
+
+        // nor does the leaderboard give away the seed
+        game.set_leaderboard(LeaderboardDb::open(Path::new(":memory:")).unwrap())
+            .await;
+        let times = game
+            .state
+            .leaderboard(LeaderboardRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(times.maze.map(|maze| maze.seed), Some(0));
+
+        // and a client that already left sees nobody
+        let seen = game
+            .state
+            .through_fog(
+                addr(9),
+                GameUpdate::snapshot(game.state.collect_players().await),
+            )
+            .await;
+        match &seen[..] {
+            [GameUpdate {
+                update: Some(game_update::Update::Snapshot(Snapshot { players })),
+                ..
+            }] => assert!(players.iter().all(|player| player.pos.is_none())),
+            other => panic!("expected a snapshot without positions, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejoining_in_the_fog_remembers_what_was_seen() {
+        // a longer corridor, too long to see from one end to the other
+        let game = GameService::new(Config {
+            maze_width: 3,
+            sight_radius: 1,
+            ..corridor_config()
+        });
+        game.state.join(addr(1), join_request()).await.unwrap();
+        for seq in 1..=3 {
+            let right = InputDirection {
+                direction: Direction::Right as i32,
+                seq,
+            };
+            game.state.apply_input(addr(1), right).await.unwrap();
+        }
+        game.state
+            .disconnect(addr(1), DisconnectReason::Quit)
+            .await
+            .unwrap();
+
+        let maze = game
+            .state
+            .join(addr(2), join_request())
+            .await
+            .unwrap()
+            .maze
+            .unwrap();
+        // out of sight from where the player left off, but seen on the way there
+        assert_eq!(maze.get(1, 1), CellType::Open);
+        assert_eq!(maze.get(5, 1), CellType::Open);
+    }
+
+    #[tokio::test]
+    async fn disconnecting_unknown_player_is_harmless() {
+        let game = game();
+        assert!(game
+            .state
+            .disconnect(addr(1), DisconnectReason::Quit)
+            .await
+            .is_none());
+        game.state.join(addr(1), join_request()).await.unwrap();
+        let player = game
+            .state
+            .disconnect(addr(1), DisconnectReason::Quit)
+            .await
+            .unwrap();
+        assert!(!player.alive);
+        assert!(game.state.players.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn idle_players_are_warned_then_kicked() {
+        let game = GameService::new(Config {
+            idle_timeout_secs: 60,
+            idle_warning_secs: 10,
+            ..Config::default()
+        });
+        let mut rx = game.state.tx.subscribe();
+        let player_id = game
+            .state
+            .join(addr(1), join_request())
+            .await
+            .unwrap()
+            .player_id;
+        rx.recv().await.unwrap();
+        let start = Instant::now();
+
+        game.state.check_idle(start + Duration::from_secs(49)).await;
+        assert!(rx.try_recv().is_err());
+
+        game.state.check_idle(start + Duration::from_secs(51)).await;
+        match rx.try_recv().unwrap().update {
+            Some(game_update::Update::IdleWarning(warning)) => {
+                assert_eq!(warning.player_id, player_id);
+                assert!(warning.seconds_left <= 10);
+            }
+            other => panic!("Expected an idle warning, got {:?}", other),
+        }
+        // only warned once
+        game.state.check_idle(start + Duration::from_secs(52)).await;
+        assert!(rx.try_recv().is_err());
+
+        game.state.check_idle(start + Duration::from_secs(61)).await;
+        match rx.try_recv().unwrap().update {
+            Some(game_update::Update::Player(player)) => {
+                assert!(!player.alive);
+                assert_eq!(player.disconnect_reason(), DisconnectReason::Idle);
+            }
+            other => panic!("Expected a kicked player, got {:?}", other),
+        }
+        assert!(game.state.players.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn input_resets_idle_timer() {
+        let game = GameService::new(Config {
+            idle_timeout_secs: 60,
+            idle_warning_secs: 10,
+            ..Config::default()
+        });
+        game.state.join(addr(1), join_request()).await.unwrap();
+        game.state.apply_input(addr(1), input(0)).await.unwrap();
+        let active = game.state.players.read().await[&addr(1)]
+            .read()
+            .await
+            .last_active;
+        game.state
+            .check_idle(active + Duration::from_secs(59))
+            .await;
+        assert_eq!(game.state.players.read().await.len(), 1);
+    }
+}
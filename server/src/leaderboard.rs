@@ -0,0 +1,206 @@
+use mazeio_shared::{Leaderboard, LeaderboardEntry, LeaderboardKind, MazeKey};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+/// most entries returned in one page
+pub const MAX_PAGE_SIZE: u32 = 50;
+
+/// Best times and wins kept in an SQLite database, so they outlive the game
+#[derive(Debug)]
+pub struct LeaderboardDb {
+    conn: Connection,
+}
+
+impl LeaderboardDb {
+    /// Open the database at path, creating it if needed
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS best_times (
+                width INTEGER NOT NULL,
+                height INTEGER NOT NULL,
+                seed INTEGER NOT NULL,
+                maze_algorithm INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                time_ms INTEGER NOT NULL,
+                PRIMARY KEY (width, height, seed, maze_algorithm, name)
+            );
+            CREATE TABLE IF NOT EXISTS wins (
+                name TEXT PRIMARY KEY,
+                wins INTEGER NOT NULL
+            );",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// The player called name reached the goal of maze, as the first one to do so if won
+    pub fn record_exit(
+        &self,
+        maze: &MazeKey,
+        name: &str,
+        time_ms: u64,
+        won: bool,
+    ) -> rusqlite::Result<()> {
+        // sqlite integers are signed, so seeds are stored by their bits
+        self.conn.execute(
+            "INSERT INTO best_times (width, height, seed, maze_algorithm, name, time_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (width, height, seed, maze_algorithm, name)
+            DO UPDATE SET time_ms = MIN(time_ms, excluded.time_ms)",
+            params![
+                maze.width,
+                maze.height,
+                maze.seed as i64,
+                maze.maze_algorithm,
+                name,
+                time_ms as i64
+            ],
+        )?;
+        if won {
+            self.conn.execute(
+                "INSERT INTO wins (name, wins) VALUES (?1, 1)
+                ON CONFLICT (name) DO UPDATE SET wins = wins + 1",
+                params![name],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Up to limit best times on maze, fastest first, skipping the first offset
+    pub fn best_times(
+        &self,
+        maze: &MazeKey,
+        offset: u32,
+        limit: u32,
+    ) -> rusqlite::Result<Leaderboard> {
+        let key = params![
+            maze.width,
+            maze.height,
+            maze.seed as i64,
+            maze.maze_algorithm
+        ];
+        let total = self.conn.query_row(
+            "SELECT COUNT(*) FROM best_times
+            WHERE width = ?1 AND height = ?2 AND seed = ?3 AND maze_algorithm = ?4",
+            key,
+            |row| row.get(0),
+        )?;
+        let mut stmt = self.conn.prepare(
+            "SELECT name, time_ms FROM best_times
+            WHERE width = ?1 AND height = ?2 AND seed = ?3 AND maze_algorithm = ?4
+            ORDER BY time_ms, name LIMIT ?5 OFFSET ?6",
+        )?;
+        let entries = stmt
+            .query_map(
+                params![
+                    maze.width,
+                    maze.height,
+                    maze.seed as i64,
+                    maze.maze_algorithm,
+                    limit,
+                    offset
+                ],
+                |row| {
+                    Ok(LeaderboardEntry {
+                        name: row.get(0)?,
+                        time_ms: row.get::<_, i64>(1)? as u64,
+                        ..LeaderboardEntry::default()
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(page(
+            LeaderboardKind::BestTimes,
+            Some(maze.clone()),
+            entries,
+            offset,
+            total,
+        ))
+    }
+
+    /// Up to limit players with the most wins, skipping the first offset
+    pub fn wins(&self, offset: u32, limit: u32) -> rusqlite::Result<Leaderboard> {
+        let total = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM wins", [], |row| row.get(0))?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, wins FROM wins ORDER BY wins DESC, name LIMIT ?1 OFFSET ?2")?;
+        let entries = stmt
+            .query_map(params![limit, offset], |row| {
+                Ok(LeaderboardEntry {
+                    name: row.get(0)?,
+                    wins: row.get(1)?,
+                    ..LeaderboardEntry::default()
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(page(LeaderboardKind::Wins, None, entries, offset, total))
+    }
+}
+
+/// Number the entries of a page by where it starts
+fn page(
+    kind: LeaderboardKind,
+    maze: Option<MazeKey>,
+    mut entries: Vec<LeaderboardEntry>,
+    offset: u32,
+    total: u32,
+) -> Leaderboard {
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = offset + i as u32 + 1;
+    }
+    Leaderboard {
+        kind: kind.into(),
+        maze,
+        entries,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mazeio_shared::MazeAlgorithm;
+
+    #[test]
+    fn keeps_best_times_per_maze_and_counts_wins() {
+        let board = LeaderboardDb::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let maze = MazeKey {
+            width: 4,
+            height: 4,
+            seed: u64::MAX,
+            maze_algorithm: MazeAlgorithm::AldousBroder.into(),
+        };
+        let other = MazeKey { seed: 1, ..maze };
+        let other_algorithm = MazeKey {
+            maze_algorithm: MazeAlgorithm::RecursiveBacktracker.into(),
+            ..maze
+        };
+        board.record_exit(&maze, "ann", 3000, true).unwrap();
+        board.record_exit(&maze, "bob", 2000, false).unwrap();
+        board.record_exit(&maze, "ann", 5000, true).unwrap();
+        board.record_exit(&maze, "cat", 4000, false).unwrap();
+        board.record_exit(&other, "cat", 1000, true).unwrap();
+        board
+            .record_exit(&other_algorithm, "dan", 1000, false)
+            .unwrap();
+
+        let times = board.best_times(&maze, 1, 10).unwrap();
+        assert_eq!(times.total, 3);
+        let ranked: Vec<_> = times
+            .entries
+            .iter()
+            .map(|entry| (entry.rank, entry.name.as_str(), entry.time_ms))
+            .collect();
+        assert_eq!(ranked, vec![(2, "ann", 3000), (3, "cat", 4000)]);
+
+        let wins = board.wins(0, 1).unwrap();
+        assert_eq!(wins.total, 2);
+        assert_eq!(wins.entries[0].name, "ann");
+        assert_eq!(wins.entries[0].wins, 2);
+    }
+}
@@ -2,178 +2,127 @@
 extern crate mazeio_shared;
 use mazeio_shared::*;
 
+mod chat;
+mod config;
+mod error;
+mod fog;
+mod game;
+mod leaderboard;
+mod names;
+mod persist;
+mod queue;
+mod rate_limit;
+mod recorder;
+mod session;
+mod stats;
+use config::{Config, LogFormat};
+use game::*;
+
 // autogenerated from proto file
-use mazeio_proto::game_server::{Game, GameServer};
+use mazeio_proto::game_server::GameServer;
 
-// async
-use futures_util::TryStreamExt;
-use std::net::SocketAddr;
-use std::pin::Pin;
-use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
-//use tokio::time::{self, Duration};
-use tokio_stream::wrappers::BroadcastStream;
-use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tokio::sync::oneshot;
+use tokio::time::{self, Duration};
+use tonic::transport::Server;
 
 // logging
 use tracing::{debug, info, instrument, trace, warn};
 use tracing_subscriber::{self, util::SubscriberInitExt, EnvFilter};
 
-// data/collection types
-use std::collections::HashMap;
-type AtomicPlayerDict = Arc<RwLock<HashMap<SocketAddr, Arc<RwLock<Player>>>>>;
-use tokio_stream::StreamExt;
-
-#[derive(Debug)]
-pub struct GameService {
-    maze: ProtoMaze,
-    players: AtomicPlayerDict,
-    tx: broadcast::Sender<Player>,
-}
-impl GameService {
-    #[instrument]
-    fn new(maze_width: usize, maze_height: usize) -> Self {
-        let (tx, _rx) = broadcast::channel(50);
-        info!("Initializing game state");
-        Self {
-            maze: ProtoMaze::new(maze_width, maze_height),
-            players: Arc::new(RwLock::new(HashMap::new())),
-            tx,
-        }
-    }
-}
-#[tonic::async_trait]
-impl Game for GameService {
-    #[instrument(skip(self))]
-    async fn connect_player(
-        &self,
-        request: Request<JoinGameRequest>,
-    ) -> Result<Response<JoinGameResponse>, Status> {
-        // addr is how we will identify clients
-        let addr = request.remote_addr().unwrap();
-        info!("Recieved connect_player request from client at {}", addr);
-        let join_game_request: JoinGameRequest = request.into_inner();
-        let player_id = {
-            let new_player = Player::new(join_game_request.name);
-            // send new player to the broadcast
-            debug!(
-                "Broadcasting new player (id: {}) for client at {}",
-                new_player.id, addr
-            );
-            match self.tx.clone().send(new_player.clone()) {
-                Ok(_) => {}
-                Err(_) => {}
-            };
-            // insert into atomic player dict
-            let id = new_player.id.clone();
-            let mut player_dict = self.players.write().await;
-            (*player_dict).insert(addr, Arc::new(RwLock::new(new_player)));
-            id
-        };
-        // get already-joined players (including this new one)
-        debug!("Collecting already-joined players for sending");
-        let players = {
-            let player_dict = self.players.read().await;
-            let mut ps: Vec<Player> = Vec::with_capacity((*player_dict).len());
-            for player_lock in player_dict.values() {
-                let player = player_lock.read().await;
-                ps.push((*player).clone());
-            }
-            ps
-        };
-        // return response
-        debug!("Returning connect_player response to client at {}", addr);
-        Ok(Response::new(JoinGameResponse {
-            player_id: player_id,
-            maze: Some(self.maze.clone()),
-            players: players,
-        }))
-    }
-
-    type StreamGameStream =
-        Pin<Box<dyn futures_core::Stream<Item = Result<Player, Status>> + Send + 'static>>;
-    #[instrument(skip(self))]
-    async fn stream_game(
-        &self,
-        request: Request<Streaming<InputDirection>>,
-    ) -> Result<Response<Self::StreamGameStream>, Status> {
-        let addr = request.remote_addr().unwrap();
-        info!("Recieved stream_game request from client at {}", addr);
-        let mut dir_stream = request.into_inner();
-
-        // clones for moving into thread
-        {
-            let players_dict = self.players.clone();
-            let broadcast_tx = self.tx.clone();
-            let maze = self.maze.clone();
-            // read from client stream and send their new player location
-            // into broadcast channel
-            tokio::spawn(async move {
-                while let Ok(maybe_dir) = TryStreamExt::try_next(&mut dir_stream).await {
-                    let player_dict_lock = players_dict.read().await;
-                    let player = (*player_dict_lock)[&addr].clone();
-                    // println!("{:?}", maybe_dir);
-                    if let Some(indir) = maybe_dir {
-                        let mut player_lock = player.write().await;
-                        let dir = Direction::from_i32(indir.direction).unwrap();
-                        debug!("Broadcasting player movement (player_id: {}, direction: {:?}) for client at {}", (*player_lock).id, dir, addr);
-                        (*player_lock).move_if_valid(&maze, dir);
-                        broadcast_tx.send((*player_lock).clone()).unwrap();
-                    } else {
-                        break;
-                    }
-                }
-                info!(
-                    "Error on incoming stream from client at {}. Assuming client disconnected.",
-                    addr
-                );
-                let mut player_dict_lock = players_dict.write().await;
-                let player = (*player_dict_lock)[&addr].clone();
-                let mut player_lock = player.write().await;
-                (*player_lock).alive = false;
-                // We will get an error here if this is the last client dropping.
-                // We can ignore it, since it just means there are no
-                // recievers.
-                debug!("Broadcasting client death at {}", addr);
-                broadcast_tx.send((*player_lock).clone()).ok();
-                (*player_dict_lock).remove(&addr);
-            });
-        }
-
-        let broadcast_sub = self.tx.subscribe();
-        Ok(Response::new(Box::pin(
-            BroadcastStream::new(broadcast_sub)
-                .map_err(|e| tonic::Status::internal(format!("Broadcast Error: {}", e)))
-                //.map(|x| x.ok())
-                //.filter_map(|x| Some(Ok(x.unwrap()))),
-        )))
-    }
-}
-
 #[tokio::main]
 #[instrument]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
+    let config = Config::load()?;
     let ef = EnvFilter::from_default_env();
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_target(true)
         .with_level(true)
         .with_thread_ids(true)
-        .with_env_filter(ef)
-        .pretty()
-        .finish()
-        .init();
+        .with_env_filter(ef);
+    match config.log_format {
+        LogFormat::Pretty => subscriber.pretty().finish().init(),
+        LogFormat::Compact => subscriber.compact().finish().init(),
+        LogFormat::Json => subscriber.json().finish().init(),
+    }
     // tracing_subscriber::fmt::init();
 
-    let addr = "[::1]:50051".parse()?;
-    let game = GameService::new(16, 32);
+    let addr = config.bind;
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let saved = match &config.state_file {
+        Some(path) => persist::load(path)?,
+        None => None,
+    };
+    let replay_file = config.replay_file.clone();
+    let chat_blocklist = config.chat_blocklist.clone();
+    let leaderboard_db = config.leaderboard_db.clone();
+    let game = match saved {
+        Some(saved) => GameService::restore(config, saved)?,
+        None => GameService::new(config),
+    };
+    if let Some(path) = replay_file {
+        game.record_to(&path)
+            .await
+            .map_err(|e| format!("Could not create replay file {}: {}", path.display(), e))?;
+        info!("Recording replay to {}", path.display());
+    }
+    if let Some(path) = chat_blocklist {
+        let filter = chat::WordFilter::load(&path)
+            .map_err(|e| format!("Could not read chat blocklist {}: {}", path.display(), e))?;
+        game.set_chat_filter(Box::new(filter)).await;
+        info!("Filtering chat with {}", path.display());
+    }
+    if let Some(path) = leaderboard_db {
+        let leaderboard = leaderboard::LeaderboardDb::open(&path)
+            .map_err(|e| format!("Could not open leaderboard {}: {}", path.display(), e))?;
+        game.set_leaderboard(leaderboard).await;
+        info!("Keeping the leaderboard in {}", path.display());
+    }
+    game.spawn_ticker();
+    game.spawn_autosave();
     info!("Server listening on {}", addr);
     debug!("Debug log level activated");
     trace!("Trace log level activated");
-    Server::builder()
+
+    // tells the drain timeout to start counting
+    let (draining_tx, draining_rx) = oneshot::channel();
+    let signal = {
+        let game = game.clone();
+        async move {
+            shutdown_signal().await;
+            info!(
+                "Shutting down, waiting up to {:?} for clients to leave",
+                shutdown_timeout
+            );
+            game.shutdown().await;
+            draining_tx.send(()).ok();
+        }
+    };
+    let server = Server::builder()
         .accept_http1(true)
         .add_service(tonic_web::enable(GameServer::new(game)))
-        .serve(addr)
-        .await?;
+        .serve_with_shutdown(addr, signal);
+    let drain_timeout = async move {
+        match draining_rx.await {
+            Ok(()) => time::sleep(shutdown_timeout).await,
+            Err(_) => futures_util::future::pending().await,
+        }
+    };
+    tokio::select! {
+        res = server => res?,
+        _ = drain_timeout => warn!("Clients still connected after {:?}, exiting anyway", shutdown_timeout),
+    }
+    info!("Server stopped");
     Ok(())
 }
+
+/// Resolves on Ctrl-C, or never if we can't listen for it
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!(
+            "Could not listen for Ctrl-C, graceful shutdown is off: {}",
+            e
+        );
+        futures_util::future::pending::<()>().await;
+    }
+}
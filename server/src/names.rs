@@ -0,0 +1,74 @@
+use crate::error::ServerError;
+use mazeio_shared::names::{is_allowed, is_reserved, MAX_NAME_LEN};
+
+/// The name a player asked for, trimmed, or why they can't have it
+pub fn validate(name: &str) -> Result<String, ServerError> {
+    let name = name.trim();
+    let invalid = |reason: &str| Err(ServerError::InvalidName(reason.to_string()));
+    if name.is_empty() {
+        return invalid("name can't be empty");
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return invalid(&format!("name is longer than {} characters", MAX_NAME_LEN));
+    }
+    if let Some(c) = name.chars().find(|c| !is_allowed(*c)) {
+        return invalid(&format!("name can't contain {:?}", c));
+    }
+    if is_reserved(name) {
+        return invalid(&format!("{} is reserved", name));
+    }
+    Ok(name.to_string())
+}
+
+/// name, or name with the lowest number after it that isn't taken,
+/// shortened to keep it within the length limit
+pub fn disambiguate(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| {
+            let suffix = format!("-{}", n);
+            let base: String = name
+                .chars()
+                .take(MAX_NAME_LEN.saturating_sub(suffix.len()))
+                .collect();
+            base + &suffix
+        })
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_checked_and_duplicates_numbered() {
+        assert_eq!(validate("  ann  ").unwrap(), "ann");
+        assert_eq!(validate("client-#1").unwrap(), "client-#1");
+        for bad in [
+            "",
+            "   ",
+            "a\x1b[2J",
+            "abcdefghijklmnopqrstu",
+            "Admin",
+            "a/b",
+        ] {
+            assert!(
+                matches!(validate(bad), Err(ServerError::InvalidName(_))),
+                "{:?}",
+                bad
+            );
+        }
+
+        let taken = ["ann", "ann-2", "abcdefghijklmnopqrst"];
+        let is_taken = |name: &str| taken.contains(&name);
+        assert_eq!(disambiguate("bob", is_taken), "bob");
+        assert_eq!(disambiguate("ann", is_taken), "ann-3");
+        assert_eq!(
+            disambiguate("abcdefghijklmnopqrst", is_taken),
+            "abcdefghijklmnopqr-2"
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use mazeio_shared::prost::Message;
+use mazeio_shared::SavedGame;
+use std::io;
+use std::path::Path;
+
+/// Read the saved game at path, if there is one
+pub fn load(path: &Path) -> Result<Option<SavedGame>, Box<dyn std::error::Error>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(format!("Could not read state file {}: {}", path.display(), e).into())
+        }
+    };
+    let saved = SavedGame::decode(bytes.as_slice())
+        .map_err(|e| format!("Invalid state file {}: {}", path.display(), e))?;
+    Ok(Some(saved))
+}
+
+/// Write a saved game to path
+pub fn save(path: &Path, saved: &SavedGame) -> io::Result<()> {
+    // write next to it first, so a crash never leaves half a save behind
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, saved.encode_to_vec())?;
+    std::fs::rename(&tmp_path, path)
+}
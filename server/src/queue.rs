@@ -0,0 +1,111 @@
+use mazeio_shared::tokio::sync::mpsc;
+use mazeio_shared::tonic::Status;
+use mazeio_shared::{JoinGameRequest, QueueUpdate};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use crate::error::ServerError;
+
+/// Updates buffered for a waiting client. Positions only ever go down,
+/// so a client that is slow to read can safely miss some.
+const QUEUE_CHANNEL_CAPACITY: usize = 4;
+
+pub type QueueSender = mpsc::Sender<Result<QueueUpdate, Status>>;
+pub type QueueReceiver = mpsc::Receiver<Result<QueueUpdate, Status>>;
+
+/// A client waiting for a slot in a full game
+#[derive(Debug)]
+pub struct QueuedClient {
+    pub addr: SocketAddr,
+    pub request: JoinGameRequest,
+    pub tx: QueueSender,
+    /// place in line the client was last told about
+    position: u32,
+}
+
+/// Clients waiting for a full game to have room, first come first served
+#[derive(Debug)]
+pub struct JoinQueue {
+    max_len: u32,
+    clients: VecDeque<QueuedClient>,
+}
+
+impl JoinQueue {
+    /// A max_len of 0 means the queue is turned off
+    pub fn new(max_len: u32) -> Self {
+        Self {
+            max_len,
+            clients: VecDeque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Put a client at the back of the line, returning where its updates will arrive
+    pub fn push(
+        &mut self,
+        addr: SocketAddr,
+        request: JoinGameRequest,
+    ) -> Result<QueueReceiver, ServerError> {
+        if self.max_len == 0 {
+            return Err(ServerError::QueueDisabled);
+        }
+        if self.clients.iter().any(|c| c.addr == addr) {
+            return Err(ServerError::AlreadyQueued(addr));
+        }
+        if self.clients.len() >= self.max_len as usize {
+            return Err(ServerError::QueueFull(self.max_len));
+        }
+        let (tx, rx) = mpsc::channel(QUEUE_CHANNEL_CAPACITY);
+        let position = self.clients.len() as u32 + 1;
+        tx.try_send(Ok(QueueUpdate::position(position))).ok();
+        self.clients.push_back(QueuedClient {
+            addr,
+            request,
+            tx,
+            position,
+        });
+        Ok(rx)
+    }
+
+    /// Take the next client in line that is still waiting
+    pub fn pop(&mut self) -> Option<QueuedClient> {
+        while let Some(client) = self.clients.pop_front() {
+            if !client.tx.is_closed() {
+                return Some(client);
+            }
+        }
+        None
+    }
+
+    /// Put a client that could not be let in after all back at the front
+    pub fn push_front(&mut self, client: QueuedClient) {
+        self.clients.push_front(client);
+    }
+
+    /// Send every waiting client away with the given error
+    pub fn close(&mut self, err: ServerError) {
+        for client in self.clients.drain(..) {
+            client.tx.try_send(Err(err.clone().into())).ok();
+        }
+    }
+
+    /// Forget clients that stopped waiting, and tell everyone
+    /// else whose place in line changed where they are now
+    pub fn notify_positions(&mut self) {
+        self.clients.retain(|c| !c.tx.is_closed());
+        for (i, client) in self.clients.iter_mut().enumerate() {
+            let position = i as u32 + 1;
+            if client.position != position
+                && client
+                    .tx
+                    .try_send(Ok(QueueUpdate::position(position)))
+                    .is_ok()
+            {
+                client.position = position;
+            }
+        }
+    }
+}
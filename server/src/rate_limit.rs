@@ -0,0 +1,70 @@
+use mazeio_shared::tokio::time::Instant;
+
+/// Classic token bucket: holds up to `capacity` tokens, refilled
+/// continuously at `rate` tokens per second, one token per action
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket. A rate of 0 never limits anything.
+    pub fn new(rate: u32, capacity: u32, now: Instant) -> Self {
+        Self {
+            capacity: capacity.max(1) as f64,
+            rate: rate as f64,
+            tokens: capacity.max(1) as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Take a token if there is one
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mazeio_shared::tokio::time::Duration;
+
+    #[test]
+    fn allows_bursts_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 3, start);
+        assert!((0..3).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+        // one token every 100ms
+        assert!(!bucket.try_take(start + Duration::from_millis(50)));
+        assert!(bucket.try_take(start + Duration::from_millis(110)));
+        assert!(!bucket.try_take(start + Duration::from_millis(120)));
+        // refilling stops at the capacity
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.try_take(later)));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(0, 1, now);
+        assert!((0..1000).all(|_| bucket.try_take(now)));
+    }
+}
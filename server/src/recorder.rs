@@ -0,0 +1,118 @@
+use mazeio_shared::prost::Message;
+use mazeio_shared::{
+    game_update, now_millis, GameMode, GameUpdate, InputDirection, Player, ProtoMaze,
+    RecordedInput, ReplayFrame, ReplayHeader,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+/// Writes a replay file, one frame per tick in which something happened
+#[derive(Debug)]
+pub struct Recorder {
+    /// finished frames, written out on a thread of their own
+    /// so the game never waits for the disk
+    frames: mpsc::Sender<ReplayFrame>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    tick: u64,
+    inputs: Vec<RecordedInput>,
+    /// latest state of every player that changed this tick, by id
+    players: HashMap<String, Player>,
+}
+
+impl Recorder {
+    pub fn create(
+        path: &Path,
+        maze: &ProtoMaze,
+        mode: GameMode,
+        tick_rate: u32,
+    ) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = ReplayHeader {
+            maze: Some(maze.clone()),
+            mode: mode.into(),
+            started_at_ms: now_millis(),
+            tick_rate,
+        };
+        writer.write_all(&header.encode_length_delimited_to_vec())?;
+        let (frames, rx) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("replay-writer".to_string())
+            .spawn(move || write_frames(writer, rx))?;
+        Ok(Self {
+            frames,
+            writer: Some(writer),
+            tick: 0,
+            inputs: Vec::new(),
+            players: HashMap::new(),
+        })
+    }
+
+    pub fn record_input(&mut self, player_id: &str, input: &InputDirection) {
+        self.inputs.push(RecordedInput {
+            player_id: player_id.to_string(),
+            input: Some(input.clone()),
+        });
+    }
+
+    /// Remember the players in an update, anything else isn't part of the replay
+    pub fn record_update(&mut self, update: &GameUpdate) {
+        if let Some(game_update::Update::Player(player)) = &update.update {
+            self.players.insert(player.id.clone(), player.clone());
+        }
+    }
+
+    /// Send everything recorded since the last tick to be written out,
+    /// failing if writing an earlier frame failed
+    pub fn finish_tick(&mut self) -> io::Result<()> {
+        self.tick += 1;
+        if self.inputs.is_empty() && self.players.is_empty() {
+            return Ok(());
+        }
+        let frame = ReplayFrame {
+            tick: self.tick,
+            server_time_ms: now_millis(),
+            inputs: std::mem::take(&mut self.inputs),
+            players: self.players.drain().map(|(_, player)| player).collect(),
+        };
+        if self.frames.send(frame).is_err() {
+            // the writer only stops early when it ran into an error
+            return Err(join(self.writer.take())
+                .err()
+                .unwrap_or_else(|| io::Error::other("replay writer stopped")));
+        }
+        Ok(())
+    }
+
+    /// Write out the last tick and wait for everything to be on disk
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_tick()?;
+        let Self { frames, writer, .. } = self;
+        // the writer stops once it has every frame sent so far
+        drop(frames);
+        join(writer)
+    }
+}
+
+/// Wait for the writer to stop, with what it ran into if anything
+fn join(writer: Option<JoinHandle<io::Result<()>>>) -> io::Result<()> {
+    match writer.map(JoinHandle::join) {
+        Some(Ok(res)) => res,
+        Some(Err(_)) => Err(io::Error::other("replay writer panicked")),
+        None => Ok(()),
+    }
+}
+
+/// Write every frame received to writer, until the recorder is done with it
+fn write_frames(
+    mut writer: BufWriter<File>,
+    frames: mpsc::Receiver<ReplayFrame>,
+) -> io::Result<()> {
+    for frame in frames {
+        writer.write_all(&frame.encode_length_delimited_to_vec())?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,56 @@
+use mazeio_shared::tokio::time::Instant;
+use mazeio_shared::Player;
+use std::collections::HashSet;
+
+use crate::fog::Sight;
+use crate::rate_limit::TokenBucket;
+
+/// A joined player, along with what the server tracks about it
+#[derive(Debug)]
+pub struct Session {
+    pub player: Player,
+    /// last time the player sent us input
+    pub last_active: Instant,
+    /// whether the player was already warned about being idle
+    pub idle_warned: bool,
+    /// limits how fast the player can move
+    pub moves: TokenBucket,
+    /// limits how fast the player can chat
+    pub chats: TokenBucket,
+    /// number of suspicious inputs the player sent
+    pub strikes: u32,
+    /// what the player can see, only kept with fog of war
+    pub sight: Option<Sight>,
+    /// when the client last reported its round trip time
+    pub last_rtt_report: Option<Instant>,
+}
+
+/// A player who left recently, kept around so they can come back
+#[derive(Debug)]
+pub struct Departed {
+    pub player: Player,
+    pub left_at: Instant,
+    /// cells the player had seen, only kept with fog of war
+    pub seen: HashSet<(u32, u32)>,
+}
+
+impl Session {
+    pub fn new(player: Player, moves: TokenBucket, chats: TokenBucket) -> Self {
+        Self {
+            player,
+            last_active: Instant::now(),
+            idle_warned: false,
+            moves,
+            chats,
+            strikes: 0,
+            sight: None,
+            last_rtt_report: None,
+        }
+    }
+
+    /// Record that the player did something
+    pub fn touch(&mut self, now: Instant) {
+        self.last_active = now;
+        self.idle_warned = false;
+    }
+}
@@ -0,0 +1,105 @@
+use mazeio_shared::{now_millis, Player, PlayerStats, Position};
+use std::collections::HashSet;
+
+/// Everything that goes into a player's PlayerStats
+#[derive(Debug)]
+pub struct Stats {
+    pub stats: PlayerStats,
+    /// where the player is, None while they are out of the game
+    pos: Option<Position>,
+    /// cells the player has been on
+    visited: HashSet<(u32, u32)>,
+    /// ms since the unix epoch the player first joined
+    joined_at_ms: u64,
+}
+
+impl Stats {
+    /// Fresh stats for a player who just joined
+    pub fn new(player: &Player) -> Self {
+        let mut stats = Self::restore(PlayerStats {
+            player_id: player.id.clone(),
+            ..PlayerStats::default()
+        });
+        stats.join(player);
+        stats
+    }
+
+    /// Continue saved stats of a player who isn't back yet. Which cells were
+    /// visited isn't saved, so going back to them counts them again, and the
+    /// clock for the time to exit starts over.
+    pub fn restore(stats: PlayerStats) -> Self {
+        Self {
+            stats,
+            pos: None,
+            visited: HashSet::new(),
+            joined_at_ms: now_millis(),
+        }
+    }
+
+    /// The player (re)joined the game, counting the cell they start on
+    pub fn join(&mut self, player: &Player) {
+        self.stats.name = player.name.clone();
+        self.pos = None;
+        if let Some(pos) = &player.pos {
+            self.visit(pos);
+        }
+    }
+
+    pub fn leave(&mut self) {
+        self.pos = None;
+    }
+
+    pub fn pos(&self) -> Option<&Position> {
+        self.pos.as_ref()
+    }
+
+    fn visit(&mut self, pos: &Position) {
+        if self.visited.insert((pos.x, pos.y)) {
+            self.stats.cells_explored += 1;
+        }
+        self.pos = Some(pos.clone());
+    }
+
+    /// The player moved to pos
+    pub fn record_move(&mut self, pos: &Position) {
+        self.stats.moves += 1;
+        self.visit(pos);
+    }
+
+    /// The player moved onto this many other players
+    pub fn record_tags(&mut self, count: u32) {
+        self.stats.tags += count;
+    }
+
+    /// The player reached the goal, as the first one to do so if won
+    pub fn record_exit(&mut self, won: bool) {
+        self.stats.time_to_exit_ms = now_millis().saturating_sub(self.joined_at_ms).max(1);
+        if won {
+            self.stats.wins += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_new_cells_count_as_explored() {
+        let player = Player::new("tester".to_string());
+        let mut stats = Stats::new(&player);
+        assert_eq!(stats.stats.cells_explored, 1);
+        stats.record_move(&Position::new(2, 1));
+        stats.record_move(&Position::new(1, 1));
+        assert_eq!(stats.stats.moves, 2);
+        assert_eq!(stats.stats.cells_explored, 2);
+        assert_eq!(stats.pos(), Some(&Position::new(1, 1)));
+
+        stats.record_exit(true);
+        assert!(stats.stats.time_to_exit_ms > 0);
+        assert_eq!(stats.stats.wins, 1);
+
+        stats.leave();
+        assert_eq!(stats.pos(), None);
+    }
+}
@@ -1,4 +1,6 @@
 fn main() {
+    // the proto lives outside this crate, so cargo won't notice it changing by itself
+    println!("cargo:rerun-if-changed=../proto/mazeio.proto");
     tonic_build::compile_protos("../proto/mazeio.proto")
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
     // tonic_build::configure()
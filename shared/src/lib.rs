@@ -1,5 +1,6 @@
 pub use futures_core;
 pub use futures_util;
+pub use prost;
 pub use rand;
 pub use tokio;
 pub use tokio_stream;
@@ -11,17 +12,84 @@ pub mod mazeio_proto {
 }
 
 pub use mazeio_proto::{
-    CellType, Direction, InputDirection, JoinGameRequest, JoinGameResponse, Maze as ProtoMaze,
-    Player, Position,
+    client_message, game_update, queue_update, Cell, CellType, ChatMessage, ClientMessage,
+    Direction, DisconnectReason, GameMode, GameUpdate, IdleWarning, InputDirection,
+    JoinGameRequest, JoinGameResponse, Leaderboard, LeaderboardEntry, LeaderboardKind,
+    LeaderboardRequest, Maze as ProtoMaze, MazeAlgorithm, MazeKey, PingRequest, PingResponse,
+    Player, PlayerStats, Position, QueueUpdate, RecordedInput, ReplayFrame, ReplayHeader,
+    RevealedCells, SavedGame, Scoreboard, ScoreboardRequest, ServerInfo, ServerInfoRequest,
+    Snapshot,
 };
 
+/// Bumped whenever the client and server can no longer talk to each other
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Names of optional features advertised in `ServerInfo::features`
+pub mod features {
+    /// server echoes `InputDirection::seq` back in `Player::last_input_seq`
+    pub const INPUT_SEQ: &str = "input_seq";
+    /// lagging clients are resynced with a `Snapshot`
+    pub const SNAPSHOTS: &str = "snapshots";
+    /// the `Ping` RPC is available and `Player::rtt_ms` is filled in
+    pub const PING: &str = "ping";
+    /// idle players are warned, then kicked with `DisconnectReason::Idle`
+    pub const IDLE_KICK: &str = "idle_kick";
+    /// full games can be waited for with the `JoinQueue` RPC
+    pub const JOIN_QUEUE: &str = "join_queue";
+    /// `JoinGameRequest::spectate` is honored and spectator counts are sent
+    pub const SPECTATE: &str = "spectate";
+    /// `ClientMessage::chat` is passed on to everyone as a `ChatMessage`
+    pub const CHAT: &str = "chat";
+    /// `PlayerStats` are streamed and the `GetScoreboard` RPC is available
+    pub const SCOREBOARD: &str = "scoreboard";
+    /// the `GetLeaderboard` RPC is available
+    pub const LEADERBOARD: &str = "leaderboard";
+    /// players only get the cells and opponents they can see, and
+    /// `RevealedCells` as they see more
+    pub const FOG: &str = "fog";
+    /// clients may show the way to the goal and move players along it
+    /// themselves, which the server allows in some game modes only
+    pub const ASSIST: &str = "assist";
+}
+
+/// Rules for player names, enforced by the server, and followed by
+/// clients when they come up with a name themselves
+pub mod names {
+    /// longest name in characters
+    pub const MAX_NAME_LEN: usize = 20;
+    /// allowed in names besides letters and digits
+    pub const ALLOWED_PUNCTUATION: &str = " -_.#'";
+    /// names that could pass for the server talking, compared ignoring case
+    pub const RESERVED: [&str; 4] = ["server", "admin", "system", "mazeio"];
+
+    pub fn is_allowed(c: char) -> bool {
+        c.is_alphanumeric() || ALLOWED_PUNCTUATION.contains(c)
+    }
+
+    pub fn is_reserved(name: &str) -> bool {
+        RESERVED
+            .iter()
+            .any(|reserved| name.eq_ignore_ascii_case(reserved))
+    }
+}
+
 use rand::{
     distributions::{Distribution, Standard},
-    Rng,
+    rngs::StdRng,
+    Rng, SeedableRng,
 };
 use std::cmp::{max, min};
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+/// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 #[allow(unused)]
 impl Direction {
     pub fn flip(&self) -> Self {
@@ -32,6 +100,16 @@ impl Direction {
             Direction::Down => Direction::Up,
         }
     }
+    /// The direction leading from one cell to a neighbouring one
+    pub fn between(from: &Position, to: &Position) -> Option<Self> {
+        match (to.x as i64 - from.x as i64, to.y as i64 - from.y as i64) {
+            (-1, 0) => Some(Direction::Left),
+            (1, 0) => Some(Direction::Right),
+            (0, -1) => Some(Direction::Up),
+            (0, 1) => Some(Direction::Down),
+            _ => None,
+        }
+    }
 }
 impl Distribution<Direction> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Direction {
@@ -43,7 +121,7 @@ impl Distribution<Direction> for Standard {
         }
     }
 }
-#[allow(unused)]
+#[allow(unused, clippy::too_many_arguments)]
 #[inline]
 pub fn move_in_dir(
     x: &mut usize,
@@ -99,9 +177,17 @@ impl Player {
             name,
             pos: Some(Position::new(1, 1)),
             alive: true,
+            last_input_seq: 0,
+            rtt_ms: 0,
+            reached_goal: false,
+            disconnect_reason: DisconnectReason::Quit as i32,
         }
     }
     pub fn move_if_valid(&mut self, maze: &ProtoMaze, dir: Direction) -> bool {
+        // in a race, players are done once they reach the exit
+        if self.reached_goal {
+            return false;
+        }
         let mut pos = self.pos.clone().unwrap();
         pos.move_in_dir(0, 0, maze.width as usize, maze.height as usize, dir, 1);
         if maze.get(pos.x as usize, pos.y as usize) == CellType::Open {
@@ -112,33 +198,276 @@ impl Player {
     }
 }
 
+impl GameUpdate {
+    /// Wrap a player in an update stamped with the current time
+    pub fn player(player: Player) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::Player(player)),
+        }
+    }
+    /// Wrap a full player list in an update stamped with the current time
+    pub fn snapshot(players: Vec<Player>) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::Snapshot(Snapshot { players })),
+        }
+    }
+    /// Warn a player that they will be kicked soon
+    pub fn idle_warning(player_id: String, seconds_left: u32) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::IdleWarning(IdleWarning {
+                player_id,
+                seconds_left,
+            })),
+        }
+    }
+    /// Pass a chat message on to everyone
+    pub fn chat(message: ChatMessage) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::Chat(message)),
+        }
+    }
+    /// Tell everyone a player's stats changed
+    pub fn stats(stats: PlayerStats) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::Stats(stats)),
+        }
+    }
+    /// Tell a player about cells they just saw
+    pub fn revealed(revealed: RevealedCells) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::Revealed(revealed)),
+        }
+    }
+    /// Tell everyone how many spectators are watching
+    pub fn spectators(count: u32) -> Self {
+        Self {
+            server_time_ms: now_millis(),
+            update: Some(game_update::Update::Spectators(count)),
+        }
+    }
+}
+
+/// Reading replay files, see `ReplayHeader` for the format
+pub mod replay {
+    use super::{ReplayFrame, ReplayHeader};
+    use prost::{DecodeError, Message};
+
+    /// Split a replay file into its header and frames
+    pub fn read(mut bytes: &[u8]) -> Result<(ReplayHeader, Vec<ReplayFrame>), DecodeError> {
+        let header = ReplayHeader::decode_length_delimited(&mut bytes)?;
+        let mut frames = Vec::new();
+        while !bytes.is_empty() {
+            frames.push(ReplayFrame::decode_length_delimited(&mut bytes)?);
+        }
+        Ok((header, frames))
+    }
+}
+
+impl PlayerStats {
+    /// Order for the scoreboard: wins, then the fastest exit,
+    /// then the most explored, then name so it doesn't jump around
+    pub fn rank(&self, other: &Self) -> std::cmp::Ordering {
+        // 0 means no exit yet, which is worse than any time
+        let exit = |stats: &Self| match stats.time_to_exit_ms {
+            0 => u64::MAX,
+            ms => ms,
+        };
+        other
+            .wins
+            .cmp(&self.wins)
+            .then(exit(self).cmp(&exit(other)))
+            .then(other.cells_explored.cmp(&self.cells_explored))
+            .then(self.name.cmp(&other.name))
+            .then(self.player_id.cmp(&other.player_id))
+    }
+}
+
+impl ClientMessage {
+    pub fn input(input: InputDirection) -> Self {
+        Self {
+            message: Some(client_message::Message::Input(input)),
+        }
+    }
+    pub fn chat(text: String) -> Self {
+        Self {
+            message: Some(client_message::Message::Chat(text)),
+        }
+    }
+}
+
+impl QueueUpdate {
+    pub fn position(position: u32) -> Self {
+        Self {
+            update: Some(queue_update::Update::Position(position)),
+        }
+    }
+    pub fn joined(response: JoinGameResponse) -> Self {
+        Self {
+            update: Some(queue_update::Update::Joined(response)),
+        }
+    }
+}
+
+impl std::str::FromStr for MazeAlgorithm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aldous-broder" => Ok(MazeAlgorithm::AldousBroder),
+            "recursive-backtracker" => Ok(MazeAlgorithm::RecursiveBacktracker),
+            _ => Err(format!(
+                "Unknown maze algorithm '{}', expected aldous-broder or recursive-backtracker",
+                s
+            )),
+        }
+    }
+}
+
+impl std::str::FromStr for GameMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "explore" => Ok(GameMode::Explore),
+            "race" => Ok(GameMode::Race),
+            _ => Err(format!(
+                "Unknown game mode '{}', expected explore or race",
+                s
+            )),
+        }
+    }
+}
+
+impl ServerInfo {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
 #[allow(unused)]
 impl ProtoMaze {
+    /// What the maze is known by on the leaderboard, given the
+    /// algorithm it was generated with, which it doesn't keep itself
+    pub fn key(&self, algorithm: MazeAlgorithm) -> MazeKey {
+        MazeKey {
+            width: self.width,
+            height: self.height,
+            seed: self.seed,
+            maze_algorithm: algorithm.into(),
+        }
+    }
+
     pub fn get(&self, x: usize, y: usize) -> CellType {
         CellType::from_i32(self.cells[y * self.width as usize + x]).unwrap_or(CellType::Wall)
     }
     pub fn set(&mut self, x: usize, y: usize, val: CellType) {
         self.cells[y * self.width as usize + x] = val as i32;
     }
+    /// The cells on a shortest way through open cells from one cell
+    /// to another, the last being to, or None if there is no way there.
+    /// Cells not known to be open, as in fog of war, are never used.
+    pub fn shortest_path(&self, from: &Position, to: &Position) -> Option<Vec<Position>> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let index = |pos: &Position| pos.y as usize * width + pos.x as usize;
+        if from.x as usize >= width
+            || from.y as usize >= height
+            || to.x as usize >= width
+            || to.y as usize >= height
+        {
+            return None;
+        }
+        // the cell each reached cell was first reached from
+        let mut came_from: Vec<Option<Position>> = vec![None; width * height];
+        came_from[index(from)] = Some(from.clone());
+        let mut queue = std::collections::VecDeque::from([from.clone()]);
+        while let Some(pos) = queue.pop_front() {
+            if pos == *to {
+                let mut path = vec![pos];
+                while let Some(prev) = came_from[index(path.last()?)].clone() {
+                    if prev == *from {
+                        break;
+                    }
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path).filter(|_| from != to);
+            }
+            for dir in [
+                Direction::Left,
+                Direction::Right,
+                Direction::Up,
+                Direction::Down,
+            ] {
+                let mut next = pos.clone();
+                next.move_in_dir(0, 0, width - 1, height - 1, dir, 1);
+                if self.get(next.x as usize, next.y as usize) == CellType::Open
+                    && came_from[index(&next)].is_none()
+                {
+                    came_from[index(&next)] = Some(pos.clone());
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
     pub fn new(open_cells_x: usize, open_cells_y: usize) -> Self {
+        Self::generate(
+            open_cells_x,
+            open_cells_y,
+            MazeAlgorithm::AldousBroder,
+            rand::random(),
+        )
+    }
+    /// Generate a maze with the given algorithm. The same seed, size and
+    /// algorithm always give the same maze.
+    pub fn generate(
+        open_cells_x: usize,
+        open_cells_y: usize,
+        algorithm: MazeAlgorithm,
+        seed: u64,
+    ) -> Self {
         let mut width = open_cells_x * 2;
         width += (width + 1) % 2;
         let mut height = open_cells_y * 2;
         height += (height + 1) % 2;
-        let mut pos_x = 1;
-        let mut pos_y = 1;
         let mut maze = ProtoMaze {
             width: width as u32,
             height: height as u32,
-            cells: vec![CellType::Wall as i32; (width * height) as usize],
+            cells: vec![CellType::Wall as i32; width * height],
+            goal: Some(Position::new(width as u32 - 2, height as u32 - 2)),
+            seed,
         };
-        maze.set(pos_x, pos_y, CellType::Open);
+        let mut rng = StdRng::seed_from_u64(seed);
+        match algorithm {
+            MazeAlgorithm::AldousBroder => {
+                maze.carve_aldous_broder(open_cells_x, open_cells_y, &mut rng)
+            }
+            MazeAlgorithm::RecursiveBacktracker => maze.carve_recursive_backtracker(&mut rng),
+        }
+        maze
+    }
+    /// Random walk, opening a passage whenever we step onto an unvisited cell
+    fn carve_aldous_broder<R: Rng>(
+        &mut self,
+        open_cells_x: usize,
+        open_cells_y: usize,
+        rng: &mut R,
+    ) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let mut pos_x = 1;
+        let mut pos_y = 1;
+        self.set(pos_x, pos_y, CellType::Open);
         let mut total_open_cells = open_cells_x * open_cells_y - 1;
         while total_open_cells > 0 {
-            let dir: Direction = rand::random();
+            let dir: Direction = rng.gen();
             move_in_dir(&mut pos_x, &mut pos_y, 1, 1, width - 2, height - 2, &dir, 2);
-            if maze.get(pos_x, pos_y) == CellType::Wall {
-                maze.set(pos_x, pos_y, CellType::Open);
+            if self.get(pos_x, pos_y) == CellType::Wall {
+                self.set(pos_x, pos_y, CellType::Open);
                 let mut between_x = pos_x;
                 let mut between_y = pos_y;
                 move_in_dir(
@@ -151,28 +480,76 @@ impl ProtoMaze {
                     &dir.flip(),
                     1,
                 );
-                maze.set(between_x, between_y, CellType::Open);
+                self.set(between_x, between_y, CellType::Open);
                 total_open_cells -= 1;
             }
         }
-        maze
+    }
+    /// Depth first search, backtracking whenever we hit a dead end
+    fn carve_recursive_backtracker<R: Rng>(&mut self, rng: &mut R) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let mut stack = vec![(1, 1)];
+        self.set(1, 1, CellType::Open);
+        while let Some(&(x, y)) = stack.last() {
+            let unvisited: Vec<Direction> = [
+                Direction::Left,
+                Direction::Right,
+                Direction::Up,
+                Direction::Down,
+            ]
+            .into_iter()
+            .filter(|dir| {
+                let (mut next_x, mut next_y) = (x, y);
+                move_in_dir(
+                    &mut next_x,
+                    &mut next_y,
+                    1,
+                    1,
+                    width - 2,
+                    height - 2,
+                    dir,
+                    2,
+                );
+                (next_x, next_y) != (x, y) && self.get(next_x, next_y) == CellType::Wall
+            })
+            .collect();
+            if unvisited.is_empty() {
+                stack.pop();
+                continue;
+            }
+            let dir = unvisited[rng.gen_range(0..unvisited.len())];
+            let (mut next_x, mut next_y) = (x, y);
+            move_in_dir(
+                &mut next_x,
+                &mut next_y,
+                1,
+                1,
+                width - 2,
+                height - 2,
+                &dir,
+                2,
+            );
+            self.set((x + next_x) / 2, (y + next_y) / 2, CellType::Open);
+            self.set(next_x, next_y, CellType::Open);
+            stack.push((next_x, next_y));
+        }
     }
 }
-impl std::string::ToString for ProtoMaze {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for ProtoMaze {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let maze_str = self
             .cells
             .chunks(self.width as usize)
-            .map(|row| {
+            .flat_map(|row| {
                 row.iter()
                     .map(|&i| CellType::from_i32(i).unwrap_or(CellType::Wall))
                     .map(|i| i.to_char())
                     .chain(std::iter::once('\n'))
                     .collect::<Vec<char>>()
             })
-            .flatten()
             .collect::<String>();
-        maze_str
+        write!(f, "{}", maze_str)
     }
 }
 
@@ -181,6 +558,7 @@ impl CellType {
         match self {
             CellType::Wall => '\u{2588}',
             CellType::Open => ' ',
+            CellType::Unknown => '\u{2591}',
         }
     }
 }
@@ -188,9 +566,93 @@ impl CellType {
 // TODO make more tests
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    /// Flood fill from the start, counting reachable open cells
+    fn reachable_open_cells(maze: &ProtoMaze) -> usize {
+        let mut seen = std::collections::HashSet::new();
+        let mut stack = vec![(1, 1)];
+        while let Some((x, y)) = stack.pop() {
+            if maze.get(x, y) == CellType::Wall || !seen.insert((x, y)) {
+                continue;
+            }
+            stack.extend([(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]);
+        }
+        seen.len()
+    }
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn scoreboard_ranks_winners_then_fastest_then_explorers() {
+        let stats = |name: &str, wins, time_to_exit_ms, cells_explored| PlayerStats {
+            name: name.to_string(),
+            wins,
+            time_to_exit_ms,
+            cells_explored,
+            ..PlayerStats::default()
+        };
+        let mut players = [
+            stats("explorer", 0, 0, 50),
+            stats("slow", 0, 9000, 10),
+            stats("idle", 0, 0, 1),
+            stats("winner", 1, 5000, 5),
+        ];
+        players.sort_by(PlayerStats::rank);
+        let names: Vec<&str> = players.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["winner", "slow", "explorer", "idle"]);
+    }
+
+    #[test]
+    fn same_seed_gives_same_maze() {
+        for algorithm in [
+            MazeAlgorithm::AldousBroder,
+            MazeAlgorithm::RecursiveBacktracker,
+        ] {
+            let a = ProtoMaze::generate(8, 6, algorithm, 42);
+            let b = ProtoMaze::generate(8, 6, algorithm, 42);
+            assert_eq!(a, b);
+            assert_eq!(a.seed, 42);
+        }
+    }
+
+    #[test]
+    fn shortest_path_follows_open_cells() {
+        let maze = ProtoMaze::generate(8, 6, MazeAlgorithm::RecursiveBacktracker, 7);
+        let goal = maze.goal.clone().unwrap();
+        let path = maze.shortest_path(&Position::new(1, 1), &goal).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+        let mut pos = Position::new(1, 1);
+        for next in &path {
+            assert!(Direction::between(&pos, next).is_some());
+            assert_eq!(maze.get(next.x as usize, next.y as usize), CellType::Open);
+            pos = next.clone();
+        }
+        assert_eq!(maze.shortest_path(&goal, &goal), None);
+        assert_eq!(maze.shortest_path(&goal, &Position::new(0, 0)), None);
+    }
+
+    #[test]
+    fn every_cell_is_reachable() {
+        for algorithm in [
+            MazeAlgorithm::AldousBroder,
+            MazeAlgorithm::RecursiveBacktracker,
+        ] {
+            let maze = ProtoMaze::generate(8, 6, algorithm, 7);
+            let open = maze
+                .cells
+                .iter()
+                .filter(|&&c| c == CellType::Open as i32)
+                .count();
+            // a perfect maze has one passage between each pair of connected cells
+            assert_eq!(open, 8 * 6 + (8 * 6 - 1));
+            assert_eq!(reachable_open_cells(&maze), open);
+            let goal = maze.goal.clone().unwrap();
+            assert_eq!(maze.get(goal.x as usize, goal.y as usize), CellType::Open);
+        }
+    }
 }